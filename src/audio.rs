@@ -0,0 +1,164 @@
+//! Audio formats produced by text-to-speech backends.
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Encoding of synthesized audio.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum AudioFormat {
+    #[default]
+    Mp3,
+    Wav,
+}
+
+names!(AudioFormat, "audio format", {
+    Mp3 => "mp3",
+    Wav => "wav",
+});
+
+impl AudioFormat {
+    /// File name extension, without the dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            AudioFormat::Mp3 => "mp3",
+            AudioFormat::Wav => "wav",
+        }
+    }
+}
+
+/// Sample layout of PCM audio in a WAV file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct WavSpec {
+    /// 1 for integer PCM, 3 for floating point.
+    pub format: u16,
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub bits_per_sample: u16,
+}
+
+impl WavSpec {
+    /// Bytes per frame of samples across all channels.
+    pub fn block_align(self) -> usize {
+        usize::from(self.channels) * usize::from(self.bits_per_sample).div_ceil(8)
+    }
+
+    /// Bytes per second of audio.
+    pub fn byte_rate(self) -> usize {
+        self.block_align() * self.sample_rate as usize
+    }
+
+    /// How long `bytes` of sample data play for.
+    pub fn duration(self, bytes: usize) -> Duration {
+        let nanos = bytes as u128 * 1_000_000_000 / self.byte_rate() as u128;
+        Duration::from_nanos(nanos as u64)
+    }
+
+    /// Sample data of silence lasting `duration`, to the nearest frame.
+    pub fn silence(self, duration: Duration) -> Vec<u8> {
+        let frames = (duration.as_secs_f64() * f64::from(self.sample_rate)).round() as usize;
+        // Eight-bit samples are unsigned, so their midpoint is silence.
+        let byte = if self.bits_per_sample == 8 { 0x80 } else { 0 };
+        vec![byte; frames * self.block_align()]
+    }
+
+    /// The 44-byte header of a WAV file holding `data_len` bytes of samples.
+    pub fn header(self, data_len: u32) -> Vec<u8> {
+        let mut header = Vec::with_capacity(44);
+        header.extend(b"RIFF");
+        header.extend((36 + data_len).to_le_bytes());
+        header.extend(b"WAVEfmt ");
+        header.extend(16u32.to_le_bytes());
+        header.extend(self.format.to_le_bytes());
+        header.extend(self.channels.to_le_bytes());
+        header.extend(self.sample_rate.to_le_bytes());
+        header.extend((self.byte_rate() as u32).to_le_bytes());
+        header.extend((self.block_align() as u16).to_le_bytes());
+        header.extend(self.bits_per_sample.to_le_bytes());
+        header.extend(b"data");
+        header.extend(data_len.to_le_bytes());
+        header
+    }
+
+    /// Split a WAV file into its sample layout and sample data. Sizes that
+    /// run past the end, as written by programs streaming to a pipe, are
+    /// taken to mean the rest of the file.
+    pub fn parse(bytes: &[u8]) -> Result<(WavSpec, &[u8]), &'static str> {
+        if bytes.len() < 12 || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+            return Err("not a WAV file");
+        }
+        let u16_at = |at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]);
+        let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+
+        let mut spec = None;
+        let mut at = 12;
+        while at + 8 <= bytes.len() {
+            let id = &bytes[at..at + 4];
+            let start = at + 8;
+            let end = start
+                .saturating_add(u32_at(at + 4) as usize)
+                .min(bytes.len());
+            match id {
+                b"fmt " if end - start >= 16 => {
+                    let mut format = u16_at(start);
+                    // WAVE_FORMAT_EXTENSIBLE keeps the real format at the
+                    // start of its sub-format GUID.
+                    if format == 0xFFFE && end - start >= 26 {
+                        format = u16_at(start + 24);
+                    }
+                    spec = Some(WavSpec {
+                        format,
+                        channels: u16_at(start + 2),
+                        sample_rate: u32_at(start + 4),
+                        bits_per_sample: u16_at(start + 14),
+                    });
+                }
+                b"data" => {
+                    let spec = spec.ok_or("WAV data before its format")?;
+                    if !matches!(spec.format, 1 | 3) || spec.block_align() == 0 {
+                        return Err("only PCM WAV files are supported");
+                    }
+                    if spec.sample_rate == 0 {
+                        return Err("WAV file has a sample rate of zero");
+                    }
+                    return Ok((spec, &bytes[start..end]));
+                }
+                _ => {}
+            }
+            // Chunks are padded to an even length.
+            at = end + (end - start) % 2;
+        }
+        Err("WAV file has no data")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let spec = WavSpec {
+            format: 1,
+            channels: 1,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+        };
+        let mut wav = spec.header(4);
+        wav.extend([1, 2, 3, 4]);
+        assert_eq!(WavSpec::parse(&wav), Ok((spec, &[1, 2, 3, 4][..])));
+        assert_eq!(spec.duration(16000), Duration::from_secs(1));
+
+        let mut silent = WavSpec {
+            sample_rate: 0,
+            ..spec
+        }
+        .header(4);
+        silent.extend([0; 4]);
+        assert_eq!(
+            WavSpec::parse(&silent),
+            Err("WAV file has a sample rate of zero")
+        );
+    }
+}
@@ -0,0 +1,468 @@
+//! Text-to-speech backends that turn chunks into audio files.
+//!
+//! Each backend declares the [`Limits`] of the requests it accepts, which
+//! [`Chunker::limits`](crate::Chunker::limits) applies so that every chunk
+//! can be sent as it is.
+
+use crate::audio::AudioFormat;
+use crate::cache::AudioCache;
+use crate::chunk::Chunk;
+use crate::error::Error;
+use crate::output::{Manifest, ManifestEntry, MANIFEST_FILE};
+use crate::unit::Unit;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// What a backend accepts in one request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Limits {
+    /// Longest chunk, in `unit`, or `None` for no limit of its own.
+    pub max_length: Option<usize>,
+    pub unit: Unit,
+    /// Whether chunks may be SSML documents.
+    pub ssml: bool,
+}
+
+impl Limits {
+    /// Limits of `max_length` in `unit`, accepting SSML if `ssml` is set.
+    pub const fn new(max_length: Option<usize>, unit: Unit, ssml: bool) -> Self {
+        Limits {
+            max_length,
+            unit,
+            ssml,
+        }
+    }
+
+    /// Why `text` cannot be sent in one request, if it cannot.
+    fn check(&self, text: &str) -> Result<(), String> {
+        let length = self.unit.measure(text);
+        match self.max_length {
+            Some(max_length) if length > max_length => Err(format!(
+                "{length} {} is over the limit of {max_length}",
+                self.unit
+            )),
+            _ if !self.ssml && text.trim_start().starts_with("<speak") => {
+                Err("SSML is not accepted".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Something that speaks chunks.
+pub trait TtsBackend {
+    /// Name used in messages.
+    fn name(&self) -> &str;
+
+    fn limits(&self) -> Limits;
+
+    /// Everything besides the text that decides how a chunk sounds, such as
+    /// the voice and speed, so cached audio is only reused when it would
+    /// sound the same.
+    fn settings(&self) -> String;
+
+    /// Extension of the files [`synthesize`](TtsBackend::synthesize) writes,
+    /// without the dot.
+    fn extension(&self) -> &str;
+
+    /// Speak `chunk`, writing the result to `path`.
+    fn synthesize(&self, chunk: &Chunk, path: &Path) -> Result<(), Error>;
+}
+
+/// Where a chunk's audio came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AudioOrigin {
+    /// Spoken by the backend.
+    Synthesized,
+    /// Copied from the cache.
+    Cached,
+}
+
+/// Synthesize the chunks listed in `dir`'s manifest in order, recording each
+/// audio file in the manifest. A chunk the backend's [`Limits`] do not allow
+/// is an error, as it was chunked for another backend or edited since.
+/// Chunks found in `cache` are copied from it instead, and new audio is added
+/// to it. The manifest is saved after every chunk, so an interrupted run
+/// keeps what it finished. `progress` is called with each entry once its
+/// audio is written.
+pub fn synthesize_dir(
+    backend: &dyn TtsBackend,
+    dir: &Path,
+    cache: Option<&AudioCache>,
+    mut progress: impl FnMut(&ManifestEntry, AudioOrigin),
+) -> Result<Manifest, Error> {
+    let manifest_path = dir.join(MANIFEST_FILE);
+    let mut manifest = Manifest::read(&manifest_path)?;
+    for index in 0..manifest.chunks.len() {
+        let entry = &manifest.chunks[index];
+        let file = dir.join(&entry.file);
+        let text = fs::read_to_string(&file).map_err(|error| Error::io(&file, error))?;
+        let chunk = entry.chunk(text);
+        backend.limits().check(&chunk.text).map_err(|message| {
+            Error::backend(
+                backend.name(),
+                format!(
+                    "{}: {message}; chunk it again for this backend",
+                    file.display()
+                ),
+            )
+        })?;
+        let audio = Path::new(&entry.file).with_extension(backend.extension());
+        if audio == Path::new(&entry.file) {
+            return Err(Error::invalid(format!(
+                "{}: would overwrite the chunk text",
+                file.display()
+            )));
+        }
+        let path = dir.join(&audio);
+        let origin = match cache.and_then(|cache| cache.get(backend, &chunk)) {
+            Some(cached) => {
+                fs::copy(cached, &path).map_err(|error| Error::io(&path, error))?;
+                AudioOrigin::Cached
+            }
+            None => {
+                backend
+                    .synthesize(&chunk, &path)
+                    .map_err(|error| error.at(file.display()))?;
+                if let Some(cache) = cache {
+                    cache.put(backend, &chunk, &path)?;
+                }
+                AudioOrigin::Synthesized
+            }
+        };
+        manifest.chunks[index].hash = Some(chunk.hash);
+        manifest.chunks[index].audio = Some(audio.to_string_lossy().into_owned());
+        manifest.write(&manifest_path)?;
+        progress(&manifest.chunks[index], origin);
+    }
+    Ok(manifest)
+}
+
+/// Writes each chunk's text to a file instead of speaking it, to preview
+/// what would be sent.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TextBackend;
+
+impl TextBackend {
+    pub const LIMITS: Limits = Limits::new(None, Unit::Chars, true);
+}
+
+impl TtsBackend for TextBackend {
+    fn name(&self) -> &str {
+        "text"
+    }
+
+    fn limits(&self) -> Limits {
+        Self::LIMITS
+    }
+
+    fn settings(&self) -> String {
+        "text".to_string()
+    }
+
+    fn extension(&self) -> &str {
+        "spoken.txt"
+    }
+
+    fn synthesize(&self, chunk: &Chunk, path: &Path) -> Result<(), Error> {
+        fs::write(path, &chunk.text).map_err(|error| Error::io(path, error))
+    }
+}
+
+/// The espeak-ng speech synthesizer, run locally.
+#[derive(Clone, Debug)]
+pub struct Espeak {
+    program: PathBuf,
+    voice: Option<String>,
+    /// Words per minute.
+    words_per_minute: Option<u32>,
+}
+
+impl Default for Espeak {
+    fn default() -> Self {
+        Espeak {
+            program: PathBuf::from("espeak-ng"),
+            voice: None,
+            words_per_minute: None,
+        }
+    }
+}
+
+impl Espeak {
+    pub const LIMITS: Limits = Limits::new(Some(5000), Unit::Chars, true);
+
+    /// espeak-ng from the `PATH`, with its default voice and speed.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run this executable instead of `espeak-ng` from the `PATH`.
+    pub fn program(mut self, program: impl Into<PathBuf>) -> Self {
+        self.program = program.into();
+        self
+    }
+
+    /// Speak with this espeak-ng voice, such as `en-us`, instead of the
+    /// default.
+    pub fn voice(mut self, voice: Option<String>) -> Self {
+        self.voice = voice;
+        self
+    }
+
+    /// Speaking rate, where 1.0 is espeak-ng's default of 175 words per
+    /// minute.
+    pub fn speed(mut self, speed: f32) -> Self {
+        self.words_per_minute = Some((175.0 * speed).round() as u32);
+        self
+    }
+
+    fn command(&self, chunk: &Chunk, path: &Path) -> Command {
+        let mut command = Command::new(&self.program);
+        if let Some(voice) = &self.voice {
+            command.arg("-v").arg(voice);
+        }
+        if let Some(words_per_minute) = self.words_per_minute {
+            command.arg("-s").arg(words_per_minute.to_string());
+        }
+        if chunk.text.trim_start().starts_with("<speak") {
+            command.arg("-m");
+        }
+        command.arg("-w").arg(path).arg("--stdin");
+        command
+    }
+}
+
+impl TtsBackend for Espeak {
+    fn name(&self) -> &str {
+        "espeak-ng"
+    }
+
+    fn limits(&self) -> Limits {
+        Self::LIMITS
+    }
+
+    fn settings(&self) -> String {
+        format!(
+            "espeak-ng voice={} words_per_minute={}",
+            self.voice.as_deref().unwrap_or_default(),
+            self.words_per_minute.unwrap_or_default()
+        )
+    }
+
+    fn extension(&self) -> &str {
+        AudioFormat::Wav.extension()
+    }
+
+    fn synthesize(&self, chunk: &Chunk, path: &Path) -> Result<(), Error> {
+        run(self.command(chunk, path), &chunk.text)
+    }
+}
+
+/// The piper neural speech synthesizer, run locally with a voice model.
+#[derive(Clone, Debug)]
+pub struct Piper {
+    program: PathBuf,
+    model: PathBuf,
+    /// Phoneme length scale; larger is slower.
+    length_scale: Option<f32>,
+}
+
+impl Piper {
+    pub const LIMITS: Limits = Limits::new(Some(2000), Unit::Chars, false);
+
+    /// Speak with the voice model at `model`, an `.onnx` file.
+    pub fn new(model: impl Into<PathBuf>) -> Self {
+        Piper {
+            program: PathBuf::from("piper"),
+            model: model.into(),
+            length_scale: None,
+        }
+    }
+
+    /// Run this executable instead of `piper` from the `PATH`.
+    pub fn program(mut self, program: impl Into<PathBuf>) -> Self {
+        self.program = program.into();
+        self
+    }
+
+    /// Speaking rate, where 1.0 is the model's own.
+    pub fn speed(mut self, speed: f32) -> Self {
+        self.length_scale = Some(1.0 / speed);
+        self
+    }
+
+    fn command(&self, path: &Path) -> Command {
+        let mut command = Command::new(&self.program);
+        command.arg("--model").arg(&self.model);
+        if let Some(length_scale) = self.length_scale {
+            command.arg("--length_scale").arg(length_scale.to_string());
+        }
+        command.arg("--output_file").arg(path);
+        command
+    }
+}
+
+impl TtsBackend for Piper {
+    fn name(&self) -> &str {
+        "piper"
+    }
+
+    fn limits(&self) -> Limits {
+        Self::LIMITS
+    }
+
+    fn settings(&self) -> String {
+        format!(
+            "piper model={} length_scale={}",
+            self.model.display(),
+            self.length_scale.unwrap_or(1.0)
+        )
+    }
+
+    fn extension(&self) -> &str {
+        AudioFormat::Wav.extension()
+    }
+
+    fn synthesize(&self, chunk: &Chunk, path: &Path) -> Result<(), Error> {
+        // Piper reads one utterance per line.
+        let text = chunk.text.split_whitespace().collect::<Vec<_>>().join(" ");
+        run(self.command(path), &text)
+    }
+}
+
+/// Run `command` with `input` on its standard input, failing with its
+/// standard error if it does not succeed.
+fn run(mut command: Command, input: &str) -> Result<(), Error> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|error| Error::backend(&program, format!("cannot run it: {error}")))?;
+    let failed = |error| Error::backend(&program, error);
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(input.as_bytes())
+        .map_err(failed)?;
+    let output = child.wait_with_output().map_err(failed)?;
+    match output.status.success() {
+        true => Ok(()),
+        false => Err(Error::backend(
+            &program,
+            format!(
+                "failed ({}): {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Chunker, Markup};
+
+    #[test]
+    fn test_commands() {
+        let chunk = &Chunker::new().markup(Markup::Ssml).chunk("Hello.")[0];
+        let espeak = Espeak::new().voice(Some("en-gb".to_string())).speed(1.2);
+        let command = espeak.command(chunk, Path::new("out.wav"));
+        let args: Vec<_> = command.get_args().collect();
+        assert_eq!(
+            args,
+            ["-v", "en-gb", "-s", "210", "-m", "-w", "out.wav", "--stdin"]
+        );
+
+        let piper = Piper::new("amy.onnx").speed(2.0);
+        let command = piper.command(Path::new("out.wav"));
+        let args: Vec<_> = command.get_args().collect();
+        assert_eq!(
+            args,
+            [
+                "--model",
+                "amy.onnx",
+                "--length_scale",
+                "0.5",
+                "--output_file",
+                "out.wav"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_synthesize_dir() {
+        let dir = std::env::temp_dir().join(format!("listnr-backend-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let chunks = Chunker::new().limit(12).chunk("One two. Three four.");
+        crate::OutputDir::new(&dir).write("doc", &chunks).unwrap();
+
+        let cache = AudioCache::new(dir.join("cache"));
+
+        let mut written = Vec::new();
+        let manifest = synthesize_dir(&TextBackend, &dir, Some(&cache), |entry, origin| {
+            written.push((entry.audio.clone().unwrap(), origin))
+        })
+        .unwrap();
+        assert_eq!(
+            written,
+            [
+                ("0001.spoken.txt".to_string(), AudioOrigin::Synthesized),
+                ("0002.spoken.txt".to_string(), AudioOrigin::Synthesized)
+            ]
+        );
+        assert_eq!(Manifest::read(dir.join(MANIFEST_FILE)).unwrap(), manifest);
+        assert_eq!(manifest.chunks[1].hash.as_ref(), Some(&chunks[1].hash));
+        assert_eq!(
+            fs::read_to_string(dir.join("0002.spoken.txt")).unwrap(),
+            "Three four."
+        );
+
+        // Only the edited chunk is spoken again.
+        let edited = Chunker::new().limit(12).chunk("One two. Three five.");
+        crate::OutputDir::new(&dir)
+            .existing(crate::Existing::Clean)
+            .write("doc", &edited)
+            .unwrap();
+        let mut origins = Vec::new();
+        synthesize_dir(&TextBackend, &dir, Some(&cache), |_, origin| {
+            origins.push(origin)
+        })
+        .unwrap();
+        assert_eq!(origins, [AudioOrigin::Cached, AudioOrigin::Synthesized]);
+        assert_eq!(
+            fs::read_to_string(dir.join("0001.spoken.txt")).unwrap(),
+            "One two."
+        );
+
+        // Chunks made for another backend are not sent.
+        let missing = Piper::new("amy.onnx").program(dir.join("no-such-piper"));
+        let rewrite = |chunks: &[Chunk]| {
+            crate::OutputDir::new(&dir)
+                .existing(crate::Existing::Clean)
+                .write("doc", chunks)
+                .unwrap();
+            synthesize_dir(&missing, &dir, None, |_, _| {})
+                .unwrap_err()
+                .to_string()
+        };
+        let ssml = Chunker::new().markup(crate::Markup::Ssml).chunk("One two.");
+        assert!(rewrite(&ssml)
+            .ends_with("0001.txt: SSML is not accepted; chunk it again for this backend"));
+        let long = Chunker::new().limit(3000).chunk(&"word ".repeat(500));
+        assert!(rewrite(&long).contains("0001.txt: 2499 chars is over the limit of 2000"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        let error = missing.synthesize(&chunks[0], &dir.join("out.wav"));
+        assert!(matches!(
+            error,
+            Err(Error::Backend { message, .. }) if message.starts_with("cannot run")
+        ));
+    }
+}
@@ -0,0 +1,55 @@
+//! A local cache of synthesized audio, so that unchanged chunks are not
+//! spoken again.
+//!
+//! Audio is filed under a hash of the chunk's [hash](crate::Chunk::hash) and
+//! the backend's [settings](crate::TtsBackend::settings), so a chunk is only
+//! reused when the same text would be spoken the same way.
+
+use crate::backend::TtsBackend;
+use crate::chunk::{content_hash, Chunk};
+use crate::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A directory of audio files named by content.
+#[derive(Clone, Debug)]
+pub struct AudioCache {
+    dir: PathBuf,
+}
+
+impl AudioCache {
+    /// A cache kept in `dir`, which is created when audio is first added.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        AudioCache { dir: dir.into() }
+    }
+
+    /// The cache in `audio` below [`cache_dir`](crate::cache_dir), or `None`
+    /// if there is no home directory.
+    pub fn user() -> Option<Self> {
+        crate::config::cache_dir().map(|dir| Self::new(dir.join("audio")))
+    }
+
+    /// Where `backend`'s audio for `chunk` is kept.
+    pub fn path(&self, backend: &dyn TtsBackend, chunk: &Chunk) -> PathBuf {
+        let key = content_hash(&format!("{}\n{}", backend.settings(), chunk.hash));
+        self.dir
+            .join(&key[..2])
+            .join(format!("{key}.{}", backend.extension()))
+    }
+
+    /// The cached audio for `chunk`, if there is any.
+    pub fn get(&self, backend: &dyn TtsBackend, chunk: &Chunk) -> Option<PathBuf> {
+        Some(self.path(backend, chunk)).filter(|path| path.is_file())
+    }
+
+    /// Keep a copy of `audio`, spoken from `chunk`. The copy is renamed into
+    /// place, so an interrupted run never leaves partial audio in the cache.
+    pub fn put(&self, backend: &dyn TtsBackend, chunk: &Chunk, audio: &Path) -> Result<(), Error> {
+        let path = self.path(backend, chunk);
+        let dir = path.parent().expect("cache paths have a parent");
+        fs::create_dir_all(dir).map_err(|error| Error::io(dir, error))?;
+        let temp = dir.join(format!(".{}.tmp", std::process::id()));
+        fs::copy(audio, &temp).map_err(|error| Error::io(&temp, error))?;
+        fs::rename(&temp, &path).map_err(|error| Error::io(&path, error))
+    }
+}
@@ -0,0 +1,178 @@
+//! Chapter markers for joined audio, as ID3 tags or ffmetadata files.
+
+use crate::error::Error;
+use std::time::Duration;
+
+/// A stretch of joined audio under one heading.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Chapter {
+    pub title: String,
+    pub start: Duration,
+    pub end: Duration,
+}
+
+impl Chapter {
+    /// A chapter called `title` from `start` to `end` of the audio.
+    pub fn new(title: impl Into<String>, start: Duration, end: Duration) -> Self {
+        Chapter {
+            title: title.into(),
+            start,
+            end,
+        }
+    }
+}
+
+/// How chapter markers are written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ChapterFormat {
+    /// ID3 CHAP frames at the start of an MP3 file
+    Id3,
+    /// An ffmetadata file next to the audio, which M4B output also embeds
+    Ffmetadata,
+}
+
+names!(ChapterFormat, "chapter format", {
+    Id3 => "id3",
+    Ffmetadata => "ffmetadata",
+});
+
+/// An ffmetadata file listing `chapters`, with `title` as the title of the
+/// whole recording.
+pub fn ffmetadata(chapters: &[Chapter], title: Option<&str>) -> String {
+    let escape = |value: &str| {
+        value.chars().fold(String::new(), |mut escaped, c| {
+            if matches!(c, '=' | ';' | '#' | '\\' | '\n') {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+            escaped
+        })
+    };
+    let mut file = ";FFMETADATA1\n".to_string();
+    if let Some(title) = title {
+        file += &format!("title={}\n", escape(title));
+    }
+    chapters.iter().for_each(|chapter| {
+        file += &format!(
+            "\n[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle={}\n",
+            chapter.start.as_millis(),
+            chapter.end.as_millis(),
+            escape(&chapter.title)
+        )
+    });
+    file
+}
+
+/// An ID3v2.4 tag with a CHAP frame for each of `chapters`, a table of
+/// contents listing them in order, and `title` as the title of the whole
+/// recording.
+pub fn id3_tag(chapters: &[Chapter], title: Option<&str>) -> Result<Vec<u8>, Error> {
+    let count = u8::try_from(chapters.len()).map_err(|_| {
+        Error::invalid(format!(
+            "{} chapters; ID3 allows at most 255",
+            chapters.len()
+        ))
+    })?;
+    let element = |index: usize| format!("chp{index}\0");
+
+    let mut frames = Vec::new();
+    if let Some(title) = title {
+        frames.extend(text_frame(b"TIT2", title));
+    }
+    let mut toc = b"toc\0".to_vec();
+    // Top-level and ordered.
+    toc.extend([0x03, count]);
+    (0..chapters.len()).for_each(|index| toc.extend(element(index).bytes()));
+    if let Some(title) = title {
+        toc.extend(text_frame(b"TIT2", title));
+    }
+    frames.extend(frame(b"CTOC", &toc));
+    chapters
+        .iter()
+        .enumerate()
+        .try_for_each(|(index, chapter)| {
+            let millis = |time: Duration| u32::try_from(time.as_millis());
+            let mut chap = element(index).into_bytes();
+            chap.extend(millis(chapter.start)?.to_be_bytes());
+            chap.extend(millis(chapter.end)?.to_be_bytes());
+            // Byte offsets are unused.
+            chap.extend([0xFF; 8]);
+            chap.extend(text_frame(b"TIT2", &chapter.title));
+            frames.extend(frame(b"CHAP", &chap));
+            Ok::<(), std::num::TryFromIntError>(())
+        })
+        .map_err(|_| Error::invalid("chapters longer than ID3 allows"))?;
+
+    let mut tag = b"ID3\x04\x00\x00".to_vec();
+    tag.extend(synchsafe(frames.len()));
+    tag.extend(frames);
+    Ok(tag)
+}
+
+fn frame(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut frame = id.to_vec();
+    frame.extend(synchsafe(body.len()));
+    frame.extend([0, 0]);
+    frame.extend(body);
+    frame
+}
+
+/// A text frame encoded as UTF-8.
+fn text_frame(id: &[u8; 4], text: &str) -> Vec<u8> {
+    let mut body = vec![0x03];
+    body.extend(text.bytes());
+    frame(id, &body)
+}
+
+/// A size as ID3 writes it, seven bits to a byte.
+fn synchsafe(size: usize) -> [u8; 4] {
+    [21, 14, 7, 0].map(|shift| (size >> shift & 0x7F) as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chapters() -> Vec<Chapter> {
+        vec![
+            Chapter::new("Intro", Duration::ZERO, Duration::from_millis(1500)),
+            Chapter::new(
+                "Setup; a=b",
+                Duration::from_millis(1500),
+                Duration::from_secs(4),
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_ffmetadata() {
+        assert_eq!(
+            ffmetadata(&chapters(), Some("Guide")),
+            ";FFMETADATA1\ntitle=Guide\n\
+             \n[CHAPTER]\nTIMEBASE=1/1000\nSTART=0\nEND=1500\ntitle=Intro\n\
+             \n[CHAPTER]\nTIMEBASE=1/1000\nSTART=1500\nEND=4000\ntitle=Setup\\; a\\=b\n"
+        );
+    }
+
+    #[test]
+    fn test_id3_tag() {
+        let tag = id3_tag(&chapters(), None).unwrap();
+        assert_eq!(&tag[..6], b"ID3\x04\x00\x00");
+        let size = tag[6..10]
+            .iter()
+            .fold(0, |size, &byte| size << 7 | byte as usize);
+        assert_eq!(size, tag.len() - 10);
+
+        // The table of contents comes first and lists both chapters.
+        assert_eq!(&tag[10..14], b"CTOC");
+        assert_eq!(&tag[20..34], b"toc\0\x03\x02chp0\0chp");
+        let chp1 = tag
+            .windows(5)
+            .rposition(|window| window == b"chp1\0")
+            .unwrap();
+        assert_eq!(&tag[chp1 + 5..chp1 + 13], [0, 0, 5, 220, 0, 0, 15, 160]);
+        assert!(tag.ends_with(b"\x03Setup; a=b"));
+    }
+}
@@ -0,0 +1,827 @@
+//! Markdown parsing and chunk assembly.
+
+use crate::backend::Limits;
+use crate::code::CodeBlockPolicy;
+use crate::front_matter::{self, FrontMatter};
+use crate::normalize::Normalization;
+use crate::render::RenderOptions;
+use crate::segment::{self, Break, ChunkConfig, Segment};
+use crate::ssml::{self, Markup};
+use crate::substitution::{self, Edit, Hit, Scope, SubstitutionMode, SubstitutionTable};
+use crate::unit::Unit;
+use comrak::nodes::{AstNode, LineColumn};
+use comrak::{nodes::NodeValue, parse_document, Arena, ComrakOptions};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::ops::{Range, RangeInclusive};
+
+/// A piece of a document small enough for a single TTS request.
+///
+/// Source positions cover every markdown block the chunk draws text from, in
+/// the original document. In [`SubstitutionMode::Source`] a block that starts
+/// or ends inside a replacement is taken to cover all of the replaced text.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[non_exhaustive]
+pub struct Chunk {
+    /// Position of the chunk in the document, starting at zero.
+    pub index: usize,
+    /// The text to speak, as plain text or an SSML document.
+    pub text: String,
+    /// SHA-256 of `text` in hex, which identifies the audio it is spoken as.
+    pub hash: String,
+    /// Length of `text` in the unit of the chunker's limit.
+    pub length: usize,
+    /// Byte offsets of the source blocks, end exclusive.
+    pub byte_range: Range<usize>,
+    /// One-based line numbers of the source blocks, end inclusive.
+    pub line_range: RangeInclusive<usize>,
+    /// Text of the headings the chunk falls under, outermost first.
+    pub heading_path: Vec<String>,
+    /// Heading of the section the chunk starts in, when splitting at headings.
+    pub title: Option<String>,
+}
+
+/// Where a block of spoken text came from in the source document.
+#[derive(Clone, Debug)]
+struct SourceBlock {
+    byte_range: Range<usize>,
+    line_range: RangeInclusive<usize>,
+    heading_path: Vec<String>,
+    title: Option<String>,
+}
+
+/// Builder for splitting markdown into [`Chunk`]s.
+#[derive(Clone, Debug, Default)]
+pub struct Chunker {
+    config: ChunkConfig,
+    markup: Markup,
+    section_level: Option<u8>,
+    render: RenderOptions,
+    code_blocks: CodeBlockPolicy,
+    substitutions: SubstitutionTable,
+    substitution_mode: SubstitutionMode,
+    substitution_scopes: Option<Vec<Scope>>,
+    normalization: Option<Normalization>,
+    /// The backend limits applied by [`limits`](Chunker::limits), which
+    /// front matter may not exceed.
+    limits: Option<Limits>,
+}
+
+impl Chunker {
+    /// A chunker with the default [`ChunkConfig`], reading plain text with no
+    /// substitutions or normalization.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the limit, unit and packing settings at once.
+    pub fn config(mut self, config: ChunkConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Maximum length of a chunk, measured in the chunker's unit. Panics if
+    /// `limit` is zero.
+    pub fn limit(mut self, limit: usize) -> Self {
+        assert!(limit > 0, "a chunk limit must be above zero");
+        self.config.limit = limit;
+        self
+    }
+
+    /// What the limit counts: characters, bytes, words or estimated tokens.
+    pub fn unit(mut self, unit: Unit) -> Self {
+        self.config.unit = unit;
+        self
+    }
+
+    /// Fit chunks to what a TTS backend accepts: its maximum length, if it
+    /// has one, and plain text if it does not read SSML.
+    pub fn limits(mut self, limits: &Limits) -> Self {
+        self.limits = Some(*limits);
+        if let Some(max_length) = limits.max_length {
+            self.config.limit = max_length;
+            self.config.unit = limits.unit;
+        }
+        if !limits.ssml {
+            self.markup = Markup::Text;
+        }
+        self
+    }
+
+    /// Emit plain text or SSML documents.
+    pub fn markup(mut self, markup: Markup) -> Self {
+        self.markup = markup;
+        self
+    }
+
+    /// Whether SSML tags count against the limit.
+    pub fn count_markup(mut self, count_markup: bool) -> Self {
+        self.config.count_markup = count_markup;
+        self
+    }
+
+    /// Start chunks at content-defined anchors as well as where the limit
+    /// requires, so that an edit only moves the chunk boundaries near it and
+    /// the chunks elsewhere keep their hashes. Chunks are somewhat shorter.
+    pub fn stable_boundaries(mut self, stable: bool) -> Self {
+        self.config.stable = stable;
+        self
+    }
+
+    /// Start a new chunk at every heading of `level` or above. Sections
+    /// that fit together share a chunk only if they have the same parent
+    /// heading.
+    pub fn section_level(mut self, level: Option<u8>) -> Self {
+        self.section_level = level;
+        self
+    }
+
+    /// How lists, links, images, tables, footnotes and HTML are read.
+    pub fn render(mut self, render: RenderOptions) -> Self {
+        self.render = render;
+        self
+    }
+
+    /// How fenced and indented code blocks are read, skipped or summarised.
+    pub fn code_blocks(mut self, policy: CodeBlockPolicy) -> Self {
+        self.code_blocks = policy;
+        self
+    }
+
+    /// Pronunciation substitutions to apply.
+    pub fn substitutions(mut self, substitutions: SubstitutionTable) -> Self {
+        self.substitutions = substitutions;
+        self
+    }
+
+    /// The substitutions in use, including any from front matter.
+    pub fn substitution_table(&self) -> &SubstitutionTable {
+        &self.substitutions
+    }
+
+    /// Whether substitutions rewrite the markdown source or the parsed text.
+    pub fn substitution_mode(mut self, mode: SubstitutionMode) -> Self {
+        self.substitution_mode = mode;
+        self
+    }
+
+    /// Restrict [`SubstitutionMode::Tree`] to `scopes`, on top of each rule's
+    /// own scopes. By default every scope a rule names is used.
+    pub fn substitution_scopes(mut self, scopes: impl IntoIterator<Item = Scope>) -> Self {
+        self.substitution_scopes = Some(scopes.into_iter().collect());
+        self
+    }
+
+    /// Spell out numbers, dates, units and the like in prose and headings,
+    /// after substitutions. Code is never normalized.
+    pub fn normalization(mut self, normalization: Option<Normalization>) -> Self {
+        self.normalization = normalization;
+        self
+    }
+
+    /// Apply a document's front matter: its chunking options override this
+    /// chunker's, and its pronunciations override the substitution table.
+    /// Under a backend's [`limits`](Chunker::limits), the document may only
+    /// lower the limit: its limit is capped at the backend's maximum, and
+    /// ignored if it is in another unit.
+    pub fn front_matter(mut self, front_matter: &FrontMatter) -> Self {
+        let options = &front_matter.chunking;
+        match self
+            .limits
+            .and_then(|limits| Some((limits.max_length?, limits.unit)))
+        {
+            Some((max_length, unit)) => {
+                if options.unit.is_none_or(|own| own == unit) {
+                    let limit = options.limit.unwrap_or(self.config.limit);
+                    self.config.limit = limit.min(max_length);
+                }
+            }
+            None => {
+                self.config.limit = options.limit.unwrap_or(self.config.limit);
+                self.config.unit = options.unit.unwrap_or(self.config.unit);
+            }
+        }
+        self.section_level = options.section_level.or(self.section_level);
+        self.code_blocks = options.code.apply(self.code_blocks);
+        self.substitutions.merge(front_matter.substitutions.clone());
+        self
+    }
+
+    /// Split a markdown document into chunks that fit the configured limit.
+    pub fn chunk(&self, content: &str) -> Vec<Chunk> {
+        self.chunk_with_hits(content).0
+    }
+
+    /// Split a markdown document into chunks, also listing the substitutions
+    /// made in it with their positions in the original document.
+    pub fn chunk_with_hits(&self, original: &str) -> (Vec<Chunk>, Vec<Hit>) {
+        // Comrak numbers lines from the end of the front matter.
+        let (front_matter, body) = front_matter::split(original);
+        let front_lines = front_matter.matches('\n').count();
+        let (content, mut hits, edits) = match self.substitution_mode {
+            SubstitutionMode::Source => {
+                let (body, hits, mut edits) =
+                    self.substitutions
+                        .substitute_with_edits(body, None, self.markup);
+                edits.iter_mut().for_each(|edit| {
+                    edit.original = shift(&edit.original, front_matter.len());
+                    edit.substituted = shift(&edit.substituted, front_matter.len());
+                });
+                (format!("{front_matter}{body}"), hits, edits)
+            }
+            SubstitutionMode::Tree => (original.to_string(), Vec::new(), Vec::new()),
+        };
+        let arena = Arena::new();
+        let root = parse_document(&arena, &content, &options(&content));
+        if self.substitution_mode == SubstitutionMode::Tree && !self.substitutions.is_empty() {
+            hits = self.substitute(root);
+        }
+        hits.iter_mut().for_each(|hit| hit.line += front_lines);
+        if let Some(normalization) = &self.normalization {
+            normalize(root, normalization);
+        }
+
+        let (segments, mut blocks) = self.collect_blocks(root, &content, front_lines);
+        if !edits.is_empty() {
+            restore_positions(&mut blocks, &edits, original);
+        }
+        let limit = self.config.limit;
+        let anchor = |current: &str, text: &str| {
+            self.config.stable
+                && segment::breaks_at_anchor(self.measure(current), text, self.measure(text), limit)
+        };
+        let chunks = segment::pack(segments, &|text| self.measure(text) <= limit, &anchor)
+            .into_iter()
+            .enumerate()
+            .map(|(index, packed)| {
+                let first = &blocks[*packed.blocks.start()];
+                let last = &blocks[*packed.blocks.end()];
+                let length = self.measure(&packed.text);
+                let text = match self.markup {
+                    Markup::Text => packed.text,
+                    Markup::Ssml => ssml::render(&packed.text),
+                };
+                Chunk {
+                    index,
+                    length,
+                    hash: content_hash(&text),
+                    text,
+                    byte_range: first.byte_range.start..last.byte_range.end,
+                    line_range: *first.line_range.start()..=*last.line_range.end(),
+                    heading_path: first.heading_path.clone(),
+                    title: first.title.clone(),
+                }
+            })
+            .collect();
+        (chunks, hits)
+    }
+
+    /// Apply substitutions to the text of each node in scope: text under a
+    /// heading or elsewhere, inline code, code blocks and link destinations.
+    /// Hit positions are approximate where the source escapes characters.
+    fn substitute<'a>(&self, root: &'a AstNode<'a>) -> Vec<Hit> {
+        let mut hits = Vec::new();
+        root.descendants().for_each(|node| {
+            let in_heading = node
+                .ancestors()
+                .any(|node| matches!(node.data.borrow().value, NodeValue::Heading(_)));
+            let start = node.data.borrow().sourcepos.start;
+            let mut substitute = |text: &mut String, scope: Scope, start: LineColumn| {
+                if self
+                    .substitution_scopes
+                    .as_ref()
+                    .is_some_and(|scopes| !scopes.contains(&scope))
+                {
+                    return;
+                }
+                let (substituted, node_hits) =
+                    self.substitutions
+                        .substitute(text, Some(scope), self.markup);
+                *text = substituted;
+                hits.extend(node_hits.into_iter().map(|mut hit| {
+                    if hit.line == 1 {
+                        hit.column += start.column.saturating_sub(1);
+                    }
+                    hit.line += start.line - 1;
+                    hit
+                }));
+            };
+            match node.data.borrow_mut().value {
+                NodeValue::Text(ref mut text) if in_heading => {
+                    substitute(text, Scope::Heading, start)
+                }
+                NodeValue::Text(ref mut text) => substitute(text, Scope::Prose, start),
+                NodeValue::Code(ref mut code) => substitute(&mut code.literal, Scope::Code, start),
+                NodeValue::CodeBlock(ref mut block) => {
+                    // A fenced listing starts on the line after its fence.
+                    let start = match block.fenced {
+                        true => LineColumn {
+                            line: start.line + 1,
+                            column: 1,
+                        },
+                        false => start,
+                    };
+                    substitute(&mut block.literal, Scope::Code, start)
+                }
+                NodeValue::Link(ref mut link) => substitute(&mut link.url, Scope::Link, start),
+                _ => {}
+            }
+        });
+        hits
+    }
+
+    /// Length of packed text as it counts against the limit.
+    fn measure(&self, text: &str) -> usize {
+        let unit = self.config.unit;
+        match self.markup {
+            Markup::Text => unit.measure(text),
+            Markup::Ssml if self.config.count_markup => unit.measure(&ssml::render(text)),
+            Markup::Ssml => unit.measure(&ssml::strip(text)),
+        }
+    }
+
+    /// Collect the text of each leaf block, tagged with the boundary preceding
+    /// it, along with the block's source position. Consecutive paragraphs in
+    /// the same container are separated by a paragraph break; headings, code
+    /// blocks and container changes by a block break.
+    fn collect_blocks<'a>(
+        &self,
+        root: &'a AstNode<'a>,
+        content: &str,
+        front_lines: usize,
+    ) -> (Vec<Segment>, Vec<SourceBlock>) {
+        let line_starts: Vec<usize> = std::iter::once(0)
+            .chain(content.match_indices('\n').map(|(ii, _)| ii + 1))
+            .collect();
+        let offset = |mut position: LineColumn| {
+            position.line += front_lines;
+            let line_start = line_starts[(position.line.max(1) - 1).min(line_starts.len() - 1)];
+            (line_start + position.column.saturating_sub(1)).min(content.len())
+        };
+
+        let mut segments = Vec::new();
+        let mut blocks = Vec::new();
+        let mut headings: Vec<(u8, String)> = Vec::new();
+        // Parent headings and title of the current section, and the break
+        // to place before the next block when a section has just started.
+        let mut section: Option<(Vec<String>, String)> = None;
+        let mut section_break = None;
+        let mut previous: Option<&'a AstNode<'a>> = None;
+        root.descendants().for_each(|node| {
+            let text = match node.data.borrow().value {
+                NodeValue::Paragraph => {
+                    let Some(prefix) = self.render.paragraph_prefix(node) else {
+                        return;
+                    };
+                    let text = format!("{prefix}{}", self.render.inline_text(node, self.markup));
+                    let is_item = node.parent().is_some_and(|parent| {
+                        matches!(
+                            parent.data.borrow().value,
+                            NodeValue::Item(_) | NodeValue::TaskItem(_)
+                        )
+                    });
+                    match self.markup {
+                        Markup::Ssml if is_item => format!("{}{}", ssml::LIST_ITEM, text.trim()),
+                        _ => text,
+                    }
+                }
+                NodeValue::Heading(ref heading) => {
+                    let text = self.render.inline_text(node, self.markup);
+                    let title = ssml::strip(text.trim());
+                    headings.retain(|(level, _)| *level < heading.level);
+                    if self
+                        .section_level
+                        .is_some_and(|level| heading.level <= level)
+                    {
+                        let parent: Vec<String> =
+                            headings.iter().map(|(_, text)| text.clone()).collect();
+                        section_break = Some(match section {
+                            Some((ref previous_parent, _)) if *previous_parent == parent => {
+                                Break::Section
+                            }
+                            _ => Break::Forced,
+                        });
+                        section = Some((parent, title.clone()));
+                    }
+                    headings.push((heading.level, title));
+                    match self.markup {
+                        Markup::Text => text,
+                        Markup::Ssml => format!("{}{}", ssml::HEADING, text.trim()),
+                    }
+                }
+                NodeValue::CodeBlock(ref code_block) => {
+                    match self
+                        .code_blocks
+                        .render(&code_block.info, &code_block.literal)
+                    {
+                        Some(text) => text,
+                        None => return,
+                    }
+                }
+                NodeValue::TableRow(_) => match self.render.table_row(node, self.markup) {
+                    Some(text) => text,
+                    None => return,
+                },
+                NodeValue::HtmlBlock(ref html) => match self.render.html_block(&html.literal) {
+                    Some(text) => text,
+                    None => return,
+                },
+                _ => return,
+            };
+            if text.trim().is_empty() {
+                return;
+            }
+
+            let break_before = match (section_break.take(), previous) {
+                (Some(section_break), _) => section_break,
+                (None, Some(previous)) if is_paragraph_run(previous, node) => Break::Paragraph,
+                _ => Break::Block,
+            };
+            segments.push(Segment {
+                block: blocks.len(),
+                ..Segment::new(text.trim(), break_before)
+            });
+
+            let sourcepos = node.data.borrow().sourcepos;
+            blocks.push(SourceBlock {
+                byte_range: offset(sourcepos.start)..offset(sourcepos.end) + 1,
+                line_range: sourcepos.start.line + front_lines..=sourcepos.end.line + front_lines,
+                heading_path: headings.iter().map(|(_, text)| text.clone()).collect(),
+                title: section.as_ref().map(|(_, title)| title.clone()),
+            });
+            previous = Some(node);
+        });
+        (segments, blocks)
+    }
+}
+
+/// `range` moved `by` bytes later.
+fn shift(range: &Range<usize>, by: usize) -> Range<usize> {
+    range.start + by..range.end + by
+}
+
+/// Move the positions of `blocks`, found in the substituted document, back
+/// onto the `original` through the replacements in `edits`.
+fn restore_positions(blocks: &mut [SourceBlock], edits: &[Edit], original: &str) {
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(original.match_indices('\n').map(|(ii, _)| ii + 1))
+        .collect();
+    let line = |offset: usize| line_starts.partition_point(|&start| start <= offset);
+    blocks.iter_mut().for_each(|block| {
+        let start = substitution::original_offset(edits, block.byte_range.start, false);
+        let end = substitution::original_offset(edits, block.byte_range.end, true);
+        block.byte_range = start..end;
+        block.line_range = line(start)..=line(end.saturating_sub(1).max(start));
+    });
+}
+
+/// Parser options for `content`, whose front matter, if any, is kept out of
+/// the spoken text.
+pub(crate) fn options(content: &str) -> ComrakOptions<'static> {
+    let mut options = ComrakOptions::default();
+    options.extension.front_matter_delimiter = Some(front_matter::delimiter(content).to_string());
+    options.extension.table = true;
+    options.extension.footnotes = true;
+    options.extension.strikethrough = true;
+    options.extension.tasklist = true;
+    options
+}
+
+/// SHA-256 of `text` in hex.
+pub(crate) fn content_hash(text: &str) -> String {
+    Sha256::digest(text.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Spell out the tokens `normalization` covers in every text node.
+fn normalize<'a>(root: &'a AstNode<'a>, normalization: &Normalization) {
+    root.descendants().for_each(|node| {
+        if let NodeValue::Text(ref mut text) = node.data.borrow_mut().value {
+            *text = normalization.apply(text);
+        }
+    });
+}
+
+fn is_paragraph_run<'a>(previous: &'a AstNode<'a>, node: &'a AstNode<'a>) -> bool {
+    let is_paragraph = |node: &'a AstNode<'a>| {
+        matches!(
+            node.data.borrow().value,
+            NodeValue::Paragraph | NodeValue::TableRow(_)
+        )
+    };
+    is_paragraph(previous) && is_paragraph(node) && top_level(previous).same_node(top_level(node))
+        || is_paragraph(top_level(previous)) && is_paragraph(top_level(node))
+}
+
+/// The ancestor of `node` that is a direct child of the document.
+fn top_level<'a>(node: &'a AstNode<'a>) -> &'a AstNode<'a> {
+    node.ancestors()
+        .take_while(|node| node.parent().is_some())
+        .last()
+        .unwrap_or(node)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECTION_CHAR_LIMIT: usize = 10;
+
+    fn chunk_markdown(content: &str, config: &ChunkConfig) -> Vec<String> {
+        Chunker::new()
+            .config(*config)
+            .chunk(content)
+            .into_iter()
+            .map(|chunk| chunk.text)
+            .collect()
+    }
+
+    #[test]
+    fn test_chunk_markdown() {
+        let content = r#"This is a test of the chunking function.
+It should split the content into chunks of SECTION_CHAR_LIMIT characters or less."#;
+        let config = ChunkConfig::new(SECTION_CHAR_LIMIT, Unit::Chars);
+        let chunks = chunk_markdown(content, &config);
+
+        chunks.iter().for_each(|chunk| {
+            assert!(
+                config.fits(chunk),
+                "Chunk exceeds SECTION_CHAR_LIMIT: '{}'",
+                chunk
+            );
+        });
+        assert_eq!(
+            chunks.concat().replace(' ', ""),
+            content.split_whitespace().collect::<String>()
+        );
+    }
+
+    #[test]
+    fn test_chunk_markdown_block_boundaries() {
+        let content = "# Hi\n\nOne. Two.\n\n- Yes.\n";
+        let config = ChunkConfig::new(SECTION_CHAR_LIMIT, Unit::Chars);
+        assert_eq!(
+            chunk_markdown(content, &config),
+            vec!["Hi", "One. Two.", "Yes."]
+        );
+    }
+
+    #[test]
+    fn test_chunk_markdown_counts_chars() {
+        let content = "Grüße aus Köln.";
+        let chars = ChunkConfig::new(15, Unit::Chars);
+        assert_eq!(chunk_markdown(content, &chars), vec!["Grüße aus Köln."]);
+        let bytes = ChunkConfig::new(15, Unit::Bytes);
+        assert_eq!(chunk_markdown(content, &bytes), vec!["Grüße aus", "Köln."]);
+    }
+
+    #[test]
+    fn test_chunk_metadata() {
+        let content = "# Guide\n\n## Setup\n\nInstall it.\nThen run it.\n\n## Usage\n\nRun.\n";
+        let chunks = Chunker::new().limit(30).chunk(content);
+        let chunk = &chunks[1];
+        assert_eq!(chunk.text, "Install it. Then run it.");
+        assert_eq!(
+            &content[chunk.byte_range.clone()],
+            "Install it.\nThen run it."
+        );
+        assert_eq!(chunk.line_range, 5..=6);
+        assert_eq!(chunk.heading_path, vec!["Guide", "Setup"]);
+        assert_eq!(chunks[2].heading_path, vec!["Guide", "Usage"]);
+    }
+
+    #[test]
+    fn test_chunk_source_positions() {
+        let content = "# Guide\n\nThe API is small.\n\nUse the CLI\nfor setup, e.g. here.\n";
+        let substitutions: SubstitutionTable = [
+            ("API", "application programming interface"),
+            ("e.g. here.", "here."),
+        ]
+        .into_iter()
+        .collect();
+        let chunks = Chunker::new()
+            .limit(50)
+            .substitutions(substitutions)
+            .chunk(content);
+        let texts: Vec<&str> = chunks.iter().map(|chunk| chunk.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            [
+                "Guide",
+                "The application programming interface is small.",
+                "Use the CLI for setup, here."
+            ]
+        );
+        assert_eq!(&content[chunks[1].byte_range.clone()], "The API is small.");
+        assert_eq!(chunks[1].line_range, 3..=3);
+        assert_eq!(
+            &content[chunks[2].byte_range.clone()],
+            "Use the CLI\nfor setup, e.g. here."
+        );
+        assert_eq!(chunks[2].line_range, 5..=6);
+    }
+
+    #[test]
+    fn test_chunk_ssml() {
+        let content = "# Title\n\nSome *very* good text.\n\n- First.\n- Second.\n";
+        let chunks = Chunker::new()
+            .markup(Markup::Ssml)
+            .limit(120)
+            .chunk(content);
+        let texts: Vec<&str> = chunks.iter().map(|chunk| chunk.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            vec![
+                "<speak><break strength=\"strong\"/><emphasis level=\"strong\">Title</emphasis>\
+                 <break strength=\"medium\"/></speak>",
+                "<speak><p>Some <emphasis level=\"moderate\">very</emphasis> good text.</p></speak>",
+                "<speak><s>First.</s><break strength=\"medium\"/>\
+                 <s>Second.</s><break strength=\"medium\"/></speak>",
+            ]
+        );
+        assert!(chunks.iter().all(|chunk| chunk.length <= 120));
+
+        let uncounted = Chunker::new()
+            .markup(Markup::Ssml)
+            .count_markup(false)
+            .limit(120)
+            .chunk(content);
+        assert_eq!(uncounted.len(), 1);
+        assert_eq!(
+            uncounted[0].length,
+            "Title\n\nSome very good text.\n\nFirst.\n\nSecond.".len()
+        );
+    }
+
+    #[test]
+    fn test_chunk_structure() {
+        let content = "1. Buy [milk](https://www.example.com/milk).\n2. ![A cow](cow.png)\n\n\
+                       | Name | Age |\n|------|-----|\n| Ann  | 30  |\n\n\
+                       Noted.[^1]\n\n[^1]: A note.\n";
+        let render = RenderOptions {
+            links: crate::render::LinkStyle::Domain,
+            ..RenderOptions::default()
+        };
+        let chunks = Chunker::new().limit(45).render(render).chunk(content);
+        let texts: Vec<&str> = chunks.iter().map(|chunk| chunk.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            vec![
+                "Item 1 of 2: Buy milk (link to example.com).",
+                "Item 2 of 2: Image: A cow.",
+                "Table with columns Name, Age.",
+                "Name: Ann, Age: 30.",
+                "Noted. (footnote 1)\n\nFootnote 1: A note.",
+            ]
+        );
+        let continued = Chunker::new().chunk("3. Three.\n4. Four.\n");
+        assert_eq!(
+            continued[0].text,
+            "Item 3 of 4: Three.\n\nItem 4 of 4: Four."
+        );
+    }
+
+    #[test]
+    fn test_chunk_sections() {
+        let content = "# Book\n\nIntro.\n\n## One\n\nA.\n\n## Two\n\nB.\n\n# Appendix\n\nC.\n";
+        let chunks = Chunker::new().section_level(Some(2)).chunk(content);
+        let texts: Vec<&str> = chunks.iter().map(|chunk| chunk.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            vec!["Book\n\nIntro.", "One\n\nA.\n\nTwo\n\nB.", "Appendix\n\nC."]
+        );
+        let titles: Vec<Option<&str>> = chunks.iter().map(|chunk| chunk.title.as_deref()).collect();
+        assert_eq!(titles, vec![Some("Book"), Some("One"), Some("Appendix")]);
+    }
+
+    #[test]
+    fn test_chunk_substitution_tree() {
+        let content = "# The API\n\nCall `API()` via the [API](https://api.example.com/API).\n";
+        let substitutions: SubstitutionTable = [("API", "A P I")].into_iter().collect();
+        let render = RenderOptions {
+            links: crate::render::LinkStyle::Url,
+            ..RenderOptions::default()
+        };
+        let chunker = Chunker::new()
+            .render(render)
+            .substitutions(substitutions)
+            .substitution_mode(SubstitutionMode::Tree);
+        let chunks = chunker.chunk(content);
+        assert_eq!(
+            chunks[0].text,
+            "The A P I\n\nCall API() via the A P I (https://api.example.com/API)."
+        );
+        assert_eq!(chunks[0].heading_path, vec!["The A P I"]);
+        let (_, hits) = chunker.chunk_with_hits(content);
+        let positions: Vec<_> = hits.iter().map(|hit| (hit.line, hit.column)).collect();
+        assert_eq!(positions, [(1, 7), (3, 23)]);
+        assert_eq!(chunks[0].byte_range, 0..content.trim_end().len());
+
+        let headings_only = chunker.substitution_scopes([Scope::Heading]).chunk(content);
+        assert_eq!(
+            headings_only[0].text,
+            "The A P I\n\nCall API() via the API (https://api.example.com/API)."
+        );
+    }
+
+    #[test]
+    fn test_chunk_normalization() {
+        let content = "# Q3 2024\n\nRevenue rose 15% to $3.2M; `retry(3)` waits 5ms.\n";
+        let chunks = Chunker::new()
+            .limit(200)
+            .normalization(Some(Normalization::default()))
+            .chunk(content);
+        assert_eq!(
+            chunks[0].text,
+            "Q3 twenty twenty-four\n\nRevenue rose fifteen percent to \
+             three point two million dollars; retry(3) waits five milliseconds."
+        );
+    }
+
+    #[test]
+    fn test_chunk_backend_limits() {
+        let content = "One two three. Four five six.";
+        let chunker = Chunker::new().markup(Markup::Ssml).limit(1000);
+        let limits = Limits::new(Some(3), Unit::Words, false);
+        let texts: Vec<String> = chunker
+            .limits(&limits)
+            .chunk(content)
+            .into_iter()
+            .map(|chunk| chunk.text)
+            .collect();
+        assert_eq!(texts, ["One two three.", "Four five six."]);
+    }
+
+    #[test]
+    fn test_chunk_front_matter_backend_limits() {
+        let chunker = Chunker::new().limits(&Limits::new(Some(20), Unit::Chars, true));
+        let front_matter = |chunking: &str| {
+            let content = format!("---\nchunking:\n{chunking}\n---\n");
+            FrontMatter::from_document(&content).unwrap().unwrap()
+        };
+        let content = "The API is small. It is also fast.";
+        let texts = |chunker: Chunker| -> Vec<String> {
+            chunker
+                .chunk(content)
+                .into_iter()
+                .map(|chunk| chunk.text)
+                .collect()
+        };
+        let raised = chunker.clone().front_matter(&front_matter("  limit: 100"));
+        assert_eq!(texts(raised), ["The API is small.", "It is also fast."]);
+        let words = chunker
+            .clone()
+            .front_matter(&front_matter("  limit: 100\n  unit: words"));
+        assert_eq!(texts(words), ["The API is small.", "It is also fast."]);
+        let lowered = chunker.front_matter(&front_matter("  limit: 10"));
+        assert_eq!(texts(lowered)[0], "The API is");
+    }
+
+    #[test]
+    fn test_chunk_front_matter() {
+        let content = "---\ntitle: API guide\npronunciations:\n  API: A P I\n\
+                       chunking:\n  limit: 20\n---\n\nThe API is small. It is also fast.\n";
+        let front_matter = FrontMatter::from_document(content).unwrap().unwrap();
+        let chunks = Chunker::new().front_matter(&front_matter).chunk(content);
+        let texts: Vec<&str> = chunks.iter().map(|chunk| chunk.text.as_str()).collect();
+        assert_eq!(texts, ["The A P I is small.", "It is also fast."]);
+        assert_eq!(chunks[0].line_range, 9..=9);
+        assert_eq!(chunks[0].byte_range.start, content.find("The API").unwrap());
+    }
+
+    #[test]
+    fn test_chunk_stable_boundaries() {
+        let content = (0..200)
+            .map(|ii| format!("Paragraph {ii} has {}words.", "more ".repeat(ii * 7 % 11)))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        let changed = |chunker: &Chunker, edit: usize| {
+            let hashes = |content: &str| -> Vec<String> {
+                chunker
+                    .chunk(content)
+                    .into_iter()
+                    .map(|chunk| chunk.hash)
+                    .collect()
+            };
+            let before = hashes(&content);
+            let edited = content.replacen(
+                &format!("Paragraph {edit} has"),
+                &format!("Paragraph {edit} now has a few more words"),
+                1,
+            );
+            hashes(&edited)
+                .iter()
+                .filter(|hash| !before.contains(hash))
+                .count()
+        };
+
+        // Greedy packing can shift every boundary after an edit until it
+        // happens to line up again.
+        let greedy = Chunker::new().limit(400);
+        assert!(changed(&greedy, 90) > 10);
+        let stable = greedy.stable_boundaries(true);
+        assert!((0..200).step_by(10).all(|edit| changed(&stable, edit) <= 5));
+    }
+}
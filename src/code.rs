@@ -0,0 +1,250 @@
+//! How fenced and indented code blocks are read out.
+
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+
+/// What to speak for a code block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum CodeAction {
+    /// Speak nothing at all.
+    Skip,
+    /// Speak the placeholder, "listing omitted" by default.
+    Omit,
+    /// Read the listing as written.
+    Verbatim,
+    /// Describe the listing, as in "a 42-line Python listing".
+    Summary,
+    /// Read only the comments in the listing.
+    Comments,
+}
+
+names!(CodeAction, "code action", {
+    Skip => "skip",
+    Omit => "omit",
+    Verbatim => "verbatim",
+    Summary => "summary",
+    Comments => "comments",
+});
+
+/// Unit of the length threshold above which a listing counts as long.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum ThresholdUnit {
+    #[default]
+    Chars,
+    Lines,
+}
+
+names!(ThresholdUnit, "threshold unit", {
+    Chars => "chars",
+    Lines => "lines",
+});
+
+/// Policy for reading code blocks. A per-language action takes precedence;
+/// otherwise listings up to the threshold get the `short` action and longer
+/// ones the `long` action. Languages outside a non-empty allowlist, and
+/// those on the denylist, are skipped.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct CodeBlockPolicy {
+    /// Longest listing, in `threshold_unit`, that gets the `short` action.
+    pub threshold: usize,
+    pub threshold_unit: ThresholdUnit,
+    pub short: CodeAction,
+    pub long: CodeAction,
+    /// Text spoken for [`CodeAction::Omit`].
+    pub placeholder: String,
+    /// Introduce verbatim listings and comments with their language.
+    pub announce_language: bool,
+    /// Actions for specific info-string languages.
+    pub languages: HashMap<String, CodeAction>,
+    pub allow: HashSet<String>,
+    pub deny: HashSet<String>,
+}
+
+impl Default for CodeBlockPolicy {
+    fn default() -> Self {
+        CodeBlockPolicy {
+            threshold: 80,
+            threshold_unit: ThresholdUnit::Chars,
+            short: CodeAction::Verbatim,
+            long: CodeAction::Omit,
+            placeholder: "listing omitted".to_string(),
+            announce_language: false,
+            languages: HashMap::new(),
+            allow: HashSet::new(),
+            deny: HashSet::new(),
+        }
+    }
+}
+
+impl CodeBlockPolicy {
+    /// The text to speak for a code block with the given info string and
+    /// contents, or `None` if it is skipped.
+    pub fn render(&self, info: &str, literal: &str) -> Option<String> {
+        let language = info.split_whitespace().next().unwrap_or("").to_lowercase();
+        let lines = literal.lines().count();
+        let is_long = match self.threshold_unit {
+            ThresholdUnit::Chars => literal.chars().count() > self.threshold,
+            ThresholdUnit::Lines => lines > self.threshold,
+        };
+        let is_allowed = self.allow.is_empty() || self.allow.contains(&language);
+
+        let action = match self.languages.get(&language) {
+            _ if !is_allowed || self.deny.contains(&language) => CodeAction::Skip,
+            Some(action) => *action,
+            None if is_long => self.long,
+            None => self.short,
+        };
+        let name = display_name(&language);
+        let announce = |text: String| match (&name, self.announce_language) {
+            (Some(name), true) => format!("{name} listing: {text}"),
+            _ => text,
+        };
+
+        match action {
+            CodeAction::Skip => None,
+            CodeAction::Omit => Some(self.placeholder.clone()),
+            CodeAction::Verbatim => Some(announce(literal.to_string())),
+            CodeAction::Summary => Some(summary(lines, name.as_deref())),
+            CodeAction::Comments => {
+                let comments = comments(&language, literal);
+                (!comments.is_empty()).then(|| announce(comments))
+            }
+        }
+    }
+}
+
+/// "a 42-line Python listing", or "an 8-line listing" without a language.
+fn summary(lines: usize, name: Option<&str>) -> String {
+    let digits = lines.to_string();
+    let article = if digits.starts_with('8')
+        || digits.len() % 3 == 2 && (digits.starts_with("11") || digits.starts_with("18"))
+    {
+        "an"
+    } else {
+        "a"
+    };
+    match name {
+        Some(name) => format!("{article} {lines}-line {name} listing"),
+        None => format!("{article} {lines}-line listing"),
+    }
+}
+
+/// The spoken name of an info-string language.
+fn display_name(language: &str) -> Option<String> {
+    let name = match language {
+        "" => return None,
+        "js" | "javascript" => "JavaScript",
+        "ts" | "typescript" => "TypeScript",
+        "py" | "python" => "Python",
+        "rs" | "rust" => "Rust",
+        "sh" | "bash" | "zsh" | "shell" | "console" => "shell",
+        "cpp" | "c++" => "C++",
+        "cs" | "csharp" => "C#",
+        "golang" | "go" => "Go",
+        "sql" | "html" | "css" | "json" | "yaml" | "toml" | "xml" => {
+            return Some(language.to_uppercase())
+        }
+        language => {
+            let mut chars = language.chars();
+            return chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect());
+        }
+    };
+    Some(name.to_string())
+}
+
+/// The comment text of a listing, one comment line per sentence. A leading
+/// `*` only marks a comment line inside a `/* … */` block, so that lines such
+/// as `*ptr = 0;` are not read out.
+fn comments(language: &str, literal: &str) -> String {
+    let markers: &[&str] = match language {
+        "py" | "python" | "sh" | "bash" | "zsh" | "shell" | "ruby" | "rb" | "yaml" | "toml"
+        | "perl" | "r" | "make" | "makefile" | "dockerfile" => &["#"],
+        "sql" | "lua" | "haskell" | "hs" | "elm" => &["--"],
+        "lisp" | "clojure" | "scheme" | "elisp" => &[";"],
+        "erlang" | "tex" | "latex" | "matlab" => &["%"],
+        _ => &["///", "//!", "//", "/**", "/*", "*/"],
+    };
+    let has_blocks = markers.contains(&"/*");
+    let mut in_block = false;
+    literal
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            let continues = in_block;
+            if has_blocks {
+                in_block = match (line.rfind("/*"), line.rfind("*/")) {
+                    (Some(open), Some(close)) => open > close,
+                    (Some(_), None) => true,
+                    (None, Some(_)) => false,
+                    (None, None) => in_block,
+                };
+            }
+            let marker = markers
+                .iter()
+                .copied()
+                .chain(continues.then_some("*"))
+                .find(|marker| line.starts_with(marker))?;
+            let text = line[marker.len()..]
+                .trim_start_matches(markers[0])
+                .trim_end_matches("*/")
+                .trim();
+            (!text.is_empty()).then(|| text.to_string())
+        })
+        .map(|text| match text.ends_with(['.', '!', '?', ':']) {
+            true => text,
+            false => format!("{text}."),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_actions() {
+        let python = "# Add one\nx = 1\n# Then print\nprint(x + 1)\n";
+        let mut policy = CodeBlockPolicy::default();
+        assert_eq!(policy.render("python", python), Some(python.to_string()));
+
+        policy.threshold = 2;
+        policy.threshold_unit = ThresholdUnit::Lines;
+        assert_eq!(
+            policy.render("python", python).as_deref(),
+            Some("listing omitted")
+        );
+
+        policy.long = CodeAction::Summary;
+        assert_eq!(
+            policy.render("python", python).as_deref(),
+            Some("a 4-line Python listing")
+        );
+        assert_eq!(
+            policy.render("", &"x\n".repeat(8)).as_deref(),
+            Some("an 8-line listing")
+        );
+
+        policy.announce_language = true;
+        policy
+            .languages
+            .insert("python".to_string(), CodeAction::Comments);
+        assert_eq!(
+            policy.render("python {.numberLines}", python).as_deref(),
+            Some("Python listing: Add one. Then print.")
+        );
+
+        let c = "/*\n * Clear the pointer\n */\n*ptr = 0;\n// Done\n";
+        assert_eq!(comments("c", c), "Clear the pointer. Done.");
+
+        policy.deny.insert("mermaid".to_string());
+        assert_eq!(policy.render("mermaid", "graph TD;"), None);
+    }
+}
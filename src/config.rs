@@ -0,0 +1,26 @@
+//! Locations of user configuration and cached data.
+
+use std::env;
+use std::path::PathBuf;
+
+/// The directory holding user configuration and pronunciation dictionaries:
+/// `$XDG_CONFIG_HOME/listnr-tools`, or `~/.config/listnr-tools` when that
+/// variable is unset or not absolute. `None` if there is no home directory.
+pub fn config_dir() -> Option<PathBuf> {
+    base_dir("XDG_CONFIG_HOME", ".config")
+}
+
+/// The directory holding cached data such as synthesized audio:
+/// `$XDG_CACHE_HOME/listnr-tools`, or `~/.cache/listnr-tools`, in the same
+/// way as [`config_dir`].
+pub fn cache_dir() -> Option<PathBuf> {
+    base_dir("XDG_CACHE_HOME", ".cache")
+}
+
+fn base_dir(variable: &str, fallback: &str) -> Option<PathBuf> {
+    let base = env::var_os(variable)
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(fallback)))?;
+    Some(base.join("listnr-tools"))
+}
@@ -0,0 +1,105 @@
+//! The error type of the library.
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Why reading, chunking, writing or speaking a document failed.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// A file could not be read or written.
+    Io {
+        /// The file, when it is known.
+        path: Option<PathBuf>,
+        source: io::Error,
+    },
+    /// A file or document section could not be parsed: a dictionary, front
+    /// matter, a manifest, credentials or audio.
+    Parse {
+        /// What was being parsed, such as a path or `line 3`.
+        location: String,
+        message: String,
+    },
+    /// An option, template, pattern or list of inputs is not valid.
+    Invalid(String),
+    /// Writing would overwrite a file left by an earlier run.
+    Exists(PathBuf),
+    /// A text-to-speech backend, or a program it runs, failed.
+    Backend { backend: String, message: String },
+}
+
+impl Error {
+    pub(crate) fn io(path: impl AsRef<Path>, source: io::Error) -> Self {
+        Error::Io {
+            path: Some(path.as_ref().to_path_buf()),
+            source,
+        }
+    }
+
+    pub(crate) fn parse(location: impl fmt::Display, message: impl fmt::Display) -> Self {
+        Error::Parse {
+            location: location.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    pub(crate) fn invalid(message: impl Into<String>) -> Self {
+        Error::Invalid(message.into())
+    }
+
+    pub(crate) fn backend(backend: impl Into<String>, message: impl fmt::Display) -> Self {
+        Error::Backend {
+            backend: backend.into(),
+            message: message.to_string(),
+        }
+    }
+
+    /// The same error, with `location` in front of where a parse error
+    /// happened or in front of the message of an invalid input or failed
+    /// backend.
+    pub(crate) fn at(self, location: impl fmt::Display) -> Self {
+        match self {
+            Error::Parse {
+                location: inner,
+                message,
+            } => Error::parse(format!("{location}: {inner}"), message),
+            Error::Invalid(message) => Error::Invalid(format!("{location}: {message}")),
+            Error::Backend { backend, message } => {
+                Error::backend(backend, format!("{location}: {message}"))
+            }
+            error => error,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io {
+                path: Some(path),
+                source,
+            } => write!(f, "{}: {source}", path.display()),
+            Error::Io { path: None, source } => write!(f, "{source}"),
+            Error::Parse { location, message } => write!(f, "{location}: {message}"),
+            Error::Invalid(message) => write!(f, "{message}"),
+            Error::Exists(path) => write!(f, "{} already exists", path.display()),
+            Error::Backend { backend, message } => write!(f, "{backend}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(source: io::Error) -> Self {
+        Error::Io { path: None, source }
+    }
+}
@@ -0,0 +1,283 @@
+//! Per-document settings from YAML or TOML front matter.
+//!
+//! A document may start with a front matter block between `---` lines (YAML)
+//! or `+++` lines (TOML). Its `pronunciations` map adds substitutions over
+//! those from dictionaries, and its `chunking` table overrides the chunker's
+//! settings:
+//!
+//! ```yaml
+//! ---
+//! title: Release notes
+//! pronunciations:
+//!   Nginx: engine x
+//!   Nguyen: { replacement: ŋwiən, kind: phonetic }
+//! chunking:
+//!   limit: 800
+//!   unit: chars
+//!   section_level: 2
+//!   code: { long: summary, languages: { mermaid: skip } }
+//! ---
+//! ```
+//!
+//! Other keys are ignored. The block itself is never spoken.
+
+use crate::code::{CodeAction, CodeBlockPolicy, ThresholdUnit};
+use crate::error::Error;
+use crate::substitution::{Boundary, Case, Kind, Substitution, SubstitutionTable};
+use crate::unit::Unit;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// Name of the substitution layer made from front matter.
+pub const FRONT_MATTER_LAYER: &str = "front matter";
+
+/// Settings read from a document's front matter.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct FrontMatter {
+    pub substitutions: SubstitutionTable,
+    pub chunking: ChunkingOptions,
+}
+
+/// Chunker settings a document may override. Unset fields keep the
+/// chunker's value.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct ChunkingOptions {
+    pub limit: Option<usize>,
+    pub unit: Option<Unit>,
+    pub section_level: Option<u8>,
+    pub code: CodeOptions,
+}
+
+/// Code block policy settings a document may override.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct CodeOptions {
+    pub threshold: Option<usize>,
+    pub threshold_unit: Option<ThresholdUnit>,
+    pub short: Option<CodeAction>,
+    pub long: Option<CodeAction>,
+    pub placeholder: Option<String>,
+    pub announce_language: Option<bool>,
+    /// Added to the policy's per-language actions.
+    pub languages: HashMap<String, CodeAction>,
+    /// Added to the policy's allowlist.
+    pub allow: Vec<String>,
+    /// Added to the policy's denylist.
+    pub deny: Vec<String>,
+}
+
+impl CodeOptions {
+    /// Apply these settings over `policy`.
+    pub fn apply(&self, mut policy: CodeBlockPolicy) -> CodeBlockPolicy {
+        policy.threshold = self.threshold.unwrap_or(policy.threshold);
+        policy.threshold_unit = self.threshold_unit.unwrap_or(policy.threshold_unit);
+        policy.short = self.short.unwrap_or(policy.short);
+        policy.long = self.long.unwrap_or(policy.long);
+        if let Some(ref placeholder) = self.placeholder {
+            policy.placeholder = placeholder.clone();
+        }
+        policy.announce_language = self.announce_language.unwrap_or(policy.announce_language);
+        policy.languages.extend(
+            self.languages
+                .iter()
+                .map(|(language, action)| (language.to_lowercase(), *action)),
+        );
+        policy
+            .allow
+            .extend(self.allow.iter().map(|language| language.to_lowercase()));
+        policy
+            .deny
+            .extend(self.deny.iter().map(|language| language.to_lowercase()));
+        policy
+    }
+}
+
+/// The form of front matter, as read by serde.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Raw {
+    pronunciations: BTreeMap<String, Pronunciation>,
+    chunking: ChunkingOptions,
+}
+
+/// A `pronunciations` entry: a replacement, or a table describing the rule.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Pronunciation {
+    Replacement(String),
+    Rule {
+        replacement: String,
+        #[serde(default)]
+        kind: Kind,
+        #[serde(default)]
+        case: Case,
+        #[serde(default)]
+        boundary: Boundary,
+    },
+}
+
+impl FrontMatter {
+    /// Read the front matter of a markdown document, or `None` if it has none.
+    pub fn from_document(content: &str) -> Result<Option<Self>, Error> {
+        let (block, _) = split(content);
+        let Some(body) = block
+            .lines()
+            .next()
+            .map(|first| first.trim_end())
+            .and_then(|delimiter| {
+                let body = block.trim_end().strip_prefix(delimiter)?;
+                body.strip_suffix(delimiter).map(|body| (delimiter, body))
+            })
+        else {
+            return Ok(None);
+        };
+        let raw: Raw = match body {
+            ("+++", toml) => {
+                toml::from_str(toml).map_err(|error| Error::parse("front matter", error))?
+            }
+            (_, yaml) if yaml.trim().is_empty() => Raw::default(),
+            (_, yaml) => {
+                serde_yaml::from_str(yaml).map_err(|error| Error::parse("front matter", error))?
+            }
+        };
+        if let Some(level) = raw.chunking.section_level {
+            if !(1..=6).contains(&level) {
+                return Err(Error::parse(
+                    "front matter",
+                    format!("section_level {level} is not a heading level from 1 to 6"),
+                ));
+            }
+        }
+
+        if raw.chunking.limit == Some(0) {
+            return Err(Error::invalid("limit must be above zero").at("front matter"));
+        }
+
+        let substitutions = raw
+            .pronunciations
+            .into_iter()
+            .map(|(pattern, pronunciation)| match pronunciation {
+                Pronunciation::Replacement(replacement) => {
+                    Ok(Substitution::new(pattern, replacement))
+                }
+                Pronunciation::Rule {
+                    replacement,
+                    kind,
+                    case,
+                    boundary,
+                } => {
+                    let rule = match kind {
+                        Kind::Literal => Substitution::new(&pattern, replacement),
+                        Kind::Regex => {
+                            Substitution::regex(&pattern, replacement).map_err(|error| {
+                                Error::parse("front matter", format!("{pattern:?}: {error}"))
+                            })?
+                        }
+                        Kind::Phonetic => Substitution::phonetic(&pattern, replacement),
+                    };
+                    Ok(rule.case(case).boundary(boundary))
+                }
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let mut table = SubstitutionTable::new();
+        substitutions
+            .into_iter()
+            .for_each(|rule| table.insert_rule(rule));
+
+        Ok(Some(FrontMatter {
+            substitutions: table.layer(FRONT_MATTER_LAYER),
+            chunking: raw.chunking,
+        }))
+    }
+}
+
+/// The comrak front matter delimiter for `content`: `+++` for TOML, and `---`
+/// otherwise.
+pub(crate) fn delimiter(content: &str) -> &'static str {
+    match content.trim_start_matches('\u{feff}').starts_with("+++") {
+        true => "+++",
+        false => "---",
+    }
+}
+
+/// Split `content` into its front matter block, with the blank line after it,
+/// and the rest, the same way comrak does.
+pub(crate) fn split(content: &str) -> (&str, &str) {
+    let bom = content.len() - content.trim_start_matches('\u{feff}').len();
+    match block_len(&content[bom..], delimiter(content)) {
+        Some(len) => content.split_at(bom + len),
+        None => ("", content),
+    }
+}
+
+/// Length of the front matter block at the start of `text`.
+fn block_len(text: &str, delimiter: &str) -> Option<usize> {
+    let mut len = delimiter.len() + line_break(text.strip_prefix(delimiter)?)?;
+    len += ["\r\n", "\n"]
+        .iter()
+        .filter_map(|newline| text[len..].find(&format!("\n{delimiter}{newline}")))
+        .min()?
+        + 1
+        + delimiter.len();
+    len += line_break(&text[len..])?;
+    Some(len + line_break(&text[len..]).unwrap_or(0))
+}
+
+/// Length of the line break at the start of `text`.
+fn line_break(text: &str) -> Option<usize> {
+    ["\r\n", "\n"]
+        .iter()
+        .find(|newline| text.starts_with(*newline))
+        .map(|newline| newline.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        let content = "---\ntitle: A\n---\n\n# A\n";
+        assert_eq!(split(content), ("---\ntitle: A\n---\n\n", "# A\n"));
+        assert_eq!(split("--- \nx\n---\n"), ("", "--- \nx\n---\n"));
+        assert_eq!(
+            split("+++\nx = 1\n+++\nBody"),
+            ("+++\nx = 1\n+++\n", "Body")
+        );
+    }
+
+    #[test]
+    fn test_from_document() {
+        let yaml = "---\ntitle: Notes\npronunciations:\n  Nginx: engine x\n  \
+                    \"v(\\\\d+)\": { replacement: version $1, kind: regex }\n\
+                    chunking:\n  limit: 800\n  code: { long: summary }\n---\nBody\n";
+        let front_matter = FrontMatter::from_document(yaml).unwrap().unwrap();
+        assert_eq!(front_matter.chunking.limit, Some(800));
+        assert_eq!(front_matter.chunking.code.long, Some(CodeAction::Summary));
+        assert_eq!(
+            front_matter.substitutions.apply("Nginx v2"),
+            "engine x version 2"
+        );
+
+        let toml = "+++\n[pronunciations]\nSQL = \"sequel\"\n+++\nBody\n";
+        let front_matter = FrontMatter::from_document(toml).unwrap().unwrap();
+        assert_eq!(front_matter.substitutions.apply("SQL"), "sequel");
+
+        assert!(FrontMatter::from_document("Body\n").unwrap().is_none());
+        assert!(FrontMatter::from_document("---\nchunking: [\n---\n").is_err());
+        assert_eq!(
+            FrontMatter::from_document("---\nchunking:\n  section_level: 7\n---\n")
+                .unwrap_err()
+                .to_string(),
+            "front matter: section_level 7 is not a heading level from 1 to 6"
+        );
+        assert!(matches!(
+            FrontMatter::from_document("---\nchunking:\n  limit: 0\n---\n"),
+            Err(Error::Invalid(message)) if message == "front matter: limit must be above zero"
+        ));
+    }
+}
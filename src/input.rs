@@ -0,0 +1,224 @@
+//! Documents to read: files, directories of markdown and standard input.
+
+use crate::error::Error;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Patterns a directory's files must match when no globs are given.
+pub const DEFAULT_GLOBS: &[&str] = &["*.md", "*.markdown"];
+
+/// Where a document is read from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Source {
+    Stdin,
+    File(PathBuf),
+}
+
+/// One document of a run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Input {
+    pub source: Source,
+    /// Name of the document within a batch, unique among its inputs: the
+    /// file stem, or the path below the directory it was found in without
+    /// its extension, as in `guide/intro`. [`expand`] appends `-2`, `-3`
+    /// and so on to names already taken by earlier inputs.
+    pub name: String,
+}
+
+impl Input {
+    /// Read from standard input for `-`, otherwise from the file at `path`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        if path == Path::new("-") {
+            return Input {
+                source: Source::Stdin,
+                name: "stdin".to_string(),
+            };
+        }
+        let name = path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        Input {
+            source: Source::File(path),
+            name,
+        }
+    }
+
+    /// The document's content, read from its file or standard input.
+    pub fn read(&self) -> io::Result<String> {
+        match &self.source {
+            Source::Stdin => {
+                let mut content = String::new();
+                io::stdin().read_to_string(&mut content)?;
+                Ok(content)
+            }
+            Source::File(path) => fs::read_to_string(path),
+        }
+    }
+
+    /// The file stem, or `stdin`.
+    pub fn stem(&self) -> &str {
+        self.name.rsplit('/').next().unwrap_or(&self.name)
+    }
+
+    /// How to refer to the document in messages.
+    pub fn label(&self) -> String {
+        match &self.source {
+            Source::Stdin => "<stdin>".to_string(),
+            Source::File(path) => path.display().to_string(),
+        }
+    }
+}
+
+/// Turn command-line paths into inputs. `-` is standard input, and a
+/// directory stands for the files below it matching `globs`, or
+/// [`DEFAULT_GLOBS`] if there are none, in name order. Globs match the path
+/// relative to the directory, and `*` crosses directory separators. A file
+/// reached more than once is read only the first time, and inputs that
+/// would share a name are told apart by a numeric suffix, in order.
+pub fn expand(paths: &[PathBuf], globs: &[String]) -> Result<Vec<Input>, Error> {
+    let patterns: Vec<&str> = match globs.is_empty() {
+        true => DEFAULT_GLOBS.to_vec(),
+        false => globs.iter().map(String::as_str).collect(),
+    };
+    let mut builder = GlobSetBuilder::new();
+    patterns
+        .iter()
+        .try_for_each(|pattern| {
+            builder.add(Glob::new(pattern)?);
+            Ok::<(), globset::Error>(())
+        })
+        .map_err(|error| Error::invalid(error.to_string()))?;
+    let globs: GlobSet = builder
+        .build()
+        .map_err(|error| Error::invalid(error.to_string()))?;
+
+    let mut inputs = Vec::new();
+    paths.iter().try_for_each(|path| {
+        match path.is_dir() {
+            true => {
+                let mut files = Vec::new();
+                walk(path, &mut files).map_err(|error| Error::io(path, error))?;
+                files.sort();
+                inputs.extend(files.into_iter().filter_map(|file| {
+                    let relative = file.strip_prefix(path).ok()?;
+                    globs.is_match(relative).then(|| Input {
+                        name: relative
+                            .with_extension("")
+                            .components()
+                            .map(|component| component.as_os_str().to_string_lossy())
+                            .collect::<Vec<_>>()
+                            .join("/"),
+                        source: Source::File(file.clone()),
+                    })
+                }));
+            }
+            false => inputs.push(Input::new(path)),
+        }
+        Ok::<(), Error>(())
+    })?;
+
+    if inputs
+        .iter()
+        .filter(|input| input.source == Source::Stdin)
+        .count()
+        > 1
+    {
+        return Err(Error::invalid("standard input can only be read once"));
+    }
+    let mut seen = HashSet::new();
+    inputs.retain(|input| match &input.source {
+        Source::Stdin => true,
+        Source::File(path) => seen.insert(fs::canonicalize(path).unwrap_or_else(|_| path.clone())),
+    });
+    let taken: HashSet<String> = inputs.iter().map(|input| input.name.clone()).collect();
+    let mut used = HashSet::new();
+    inputs.iter_mut().for_each(|input| {
+        if !used.insert(input.name.clone()) {
+            let name = (2..)
+                .map(|suffix| format!("{}-{suffix}", input.name))
+                .find(|name| !taken.contains(name) && !used.contains(name))
+                .expect("some suffix is free");
+            used.insert(name.clone());
+            input.name = name;
+        }
+    });
+    Ok(inputs)
+}
+
+/// Collect the files below `dir`, skipping hidden entries and links to
+/// directories, which could lead back up the tree.
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    fs::read_dir(dir)?.try_for_each(|entry| {
+        let entry = entry?;
+        let path = entry.path();
+        let hidden = path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        let kind = entry.file_type()?;
+        match (hidden, kind.is_dir()) {
+            (true, _) => Ok(()),
+            (false, true) => walk(&path, files),
+            (false, false) if kind.is_symlink() && path.is_dir() => Ok(()),
+            (false, false) => {
+                files.push(path);
+                Ok(())
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand() {
+        let dir = std::env::temp_dir().join(format!("listnr-input-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("guide/.drafts")).unwrap();
+        [
+            "intro.md",
+            "guide/setup.md",
+            "guide/notes.txt",
+            "guide/.drafts/wip.md",
+        ]
+        .iter()
+        .for_each(|file| fs::write(dir.join(file), "Text.").unwrap());
+
+        let names = |inputs: Vec<Input>| -> Vec<String> {
+            inputs.into_iter().map(|input| input.name).collect()
+        };
+        let inputs = expand(&[dir.clone(), PathBuf::from("-")], &[]).unwrap();
+        assert_eq!(names(inputs), ["guide/setup", "intro", "stdin"]);
+        let inputs = expand(std::slice::from_ref(&dir), &["guide/*".to_string()]).unwrap();
+        assert_eq!(names(inputs), ["guide/notes", "guide/setup"]);
+        fs::write(dir.join("guide/intro.md"), "Text.").unwrap();
+        fs::write(dir.join("intro-2.md"), "Text.").unwrap();
+        let paths = [
+            dir.join("intro.md"),
+            dir.join("guide/intro.md"),
+            dir.join("intro-2.md"),
+        ];
+        assert_eq!(
+            names(expand(&paths, &[]).unwrap()),
+            ["intro", "intro-3", "intro-2"]
+        );
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&dir, dir.join("guide/loop")).unwrap();
+            let paths = [dir.clone(), dir.join("intro.md")];
+            assert_eq!(
+                names(expand(&paths, &[]).unwrap()),
+                ["guide/intro", "guide/setup", "intro-2", "intro"]
+            );
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
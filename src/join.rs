@@ -0,0 +1,401 @@
+//! Joining the audio of a document's chunks into one file with chapters.
+//!
+//! Chunk audio is joined as PCM, with silence between chunks and a longer
+//! pause where a new heading starts, and written as WAV. MP3 and M4B output
+//! is encoded from that by ffmpeg, which also decodes chunk audio that is not
+//! WAV or does not share the first chunk's sample layout.
+
+use crate::audio::WavSpec;
+use crate::chapter::{ffmetadata, id3_tag, Chapter, ChapterFormat};
+use crate::error::Error;
+use crate::output::{Manifest, ManifestEntry, MANIFEST_FILE};
+use std::fs::{self, File};
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Container {
+    Wav,
+    Mp3,
+    M4b,
+}
+
+/// Joins chunk audio into one file.
+#[derive(Clone, Debug)]
+pub struct Joiner {
+    gap: Duration,
+    heading_gap: Duration,
+    chapter_level: usize,
+    chapters: Option<ChapterFormat>,
+    title: Option<String>,
+    ffmpeg: PathBuf,
+}
+
+impl Default for Joiner {
+    fn default() -> Self {
+        Joiner {
+            gap: Duration::from_millis(500),
+            heading_gap: Duration::from_millis(1500),
+            chapter_level: 6,
+            chapters: None,
+            title: None,
+            ffmpeg: PathBuf::from("ffmpeg"),
+        }
+    }
+}
+
+impl Joiner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Silence between chunks under the same heading.
+    pub fn gap(mut self, gap: Duration) -> Self {
+        self.gap = gap;
+        self
+    }
+
+    /// Silence before a chunk under a different heading than the one before.
+    pub fn heading_gap(mut self, heading_gap: Duration) -> Self {
+        self.heading_gap = heading_gap;
+        self
+    }
+
+    /// Start chapters at headings of this level or above; deeper headings
+    /// stay within their parent's chapter.
+    pub fn chapter_level(mut self, chapter_level: usize) -> Self {
+        self.chapter_level = chapter_level;
+        self
+    }
+
+    /// Write chapter markers this way, or not at all for `None`.
+    pub fn chapters(mut self, chapters: Option<ChapterFormat>) -> Self {
+        self.chapters = chapters;
+        self
+    }
+
+    /// Title of the whole recording, also used for a chapter before the first
+    /// heading. Defaults to the output file's stem.
+    pub fn title(mut self, title: Option<String>) -> Self {
+        self.title = title;
+        self
+    }
+
+    /// Run this executable instead of `ffmpeg` from the `PATH`.
+    pub fn ffmpeg(mut self, ffmpeg: impl Into<PathBuf>) -> Self {
+        self.ffmpeg = ffmpeg.into();
+        self
+    }
+
+    /// Join the audio files listed in `dir`'s manifest, in order, into
+    /// `output`, whose extension picks the format: `wav`, `mp3` or `m4b`.
+    /// Returns the chapters, which are also written as configured.
+    pub fn join(&self, dir: &Path, output: &Path) -> Result<Vec<Chapter>, Error> {
+        let extension = output
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        let container = match extension.as_deref() {
+            Some("wav") => Container::Wav,
+            Some("mp3") => Container::Mp3,
+            Some("m4b") => Container::M4b,
+            _ => {
+                return Err(Error::invalid(format!(
+                    "{}: expected a .wav, .mp3 or .m4b file",
+                    output.display()
+                )))
+            }
+        };
+        if self.chapters == Some(ChapterFormat::Id3) && container != Container::Mp3 {
+            return Err(Error::invalid("ID3 chapters need an .mp3 output"));
+        }
+        let manifest = Manifest::read(dir.join(MANIFEST_FILE))?;
+        if manifest.chunks.is_empty() {
+            return Err(Error::invalid(format!(
+                "{}: no chunks to join",
+                dir.display()
+            )));
+        }
+        let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+        let title = self.title.as_deref().unwrap_or(&stem);
+
+        let chapters = match container {
+            Container::Wav => self.join_wav(dir, &manifest, output, title)?,
+            _ => {
+                let temp = std::env::temp_dir().join(format!("listnr-join-{}", std::process::id()));
+                let wav = temp.with_extension("wav");
+                let chapters = self
+                    .join_wav(dir, &manifest, &wav, title)
+                    .and_then(|chapters| {
+                        self.encode(
+                            &wav,
+                            &temp.with_extension("ffmetadata"),
+                            output,
+                            container,
+                            &chapters,
+                            title,
+                        )?;
+                        Ok(chapters)
+                    });
+                let _ = fs::remove_file(&wav);
+                chapters?
+            }
+        };
+        if self.chapters == Some(ChapterFormat::Ffmetadata) {
+            let path = output.with_extension("ffmetadata");
+            fs::write(&path, ffmetadata(&chapters, Some(title)))
+                .map_err(|error| Error::io(&path, error))?;
+        }
+        Ok(chapters)
+    }
+
+    /// Write the chunks' audio to `path` as one WAV file, returning where the
+    /// chapters fall.
+    fn join_wav(
+        &self,
+        dir: &Path,
+        manifest: &Manifest,
+        path: &Path,
+        title: &str,
+    ) -> Result<Vec<Chapter>, Error> {
+        let failed = |error| Error::io(path, error);
+        let mut file = BufWriter::new(File::create(path).map_err(failed)?);
+        let mut spec: Option<WavSpec> = None;
+        let mut length = 0;
+        let mut chapters: Vec<Chapter> = Vec::new();
+        let mut previous: Option<&ManifestEntry> = None;
+
+        for entry in &manifest.chunks {
+            let audio = dir.join(entry.audio.as_ref().ok_or_else(|| {
+                Error::invalid(format!("{}: no audio; synthesize it first", entry.file))
+            })?);
+            let bytes = self.decode(&audio, spec)?;
+            let (piece, data) =
+                WavSpec::parse(&bytes).map_err(|error| Error::parse(audio.display(), error))?;
+            let spec = match spec {
+                Some(spec) => spec,
+                None => {
+                    file.write_all(&piece.header(0)).map_err(failed)?;
+                    *spec.insert(piece)
+                }
+            };
+
+            if let Some(previous) = previous {
+                let gap = match previous.heading_path == entry.heading_path {
+                    true => self.gap,
+                    false => self.heading_gap,
+                };
+                let silence = spec.silence(gap);
+                file.write_all(&silence).map_err(failed)?;
+                length += silence.len();
+            }
+            let start = spec.duration(length);
+            let headings = self.chapter_headings(entry);
+            if previous.is_none_or(|previous| self.chapter_headings(previous) != headings) {
+                if let Some(last) = chapters.last_mut() {
+                    last.end = start;
+                }
+                chapters.push(Chapter::new(
+                    headings.last().map_or(title, String::as_str),
+                    start,
+                    start,
+                ));
+            }
+            file.write_all(data).map_err(failed)?;
+            length += data.len();
+            previous = Some(entry);
+        }
+
+        let spec = spec.expect("there is at least one chunk");
+        if let Some(last) = chapters.last_mut() {
+            last.end = spec.duration(length);
+        }
+        let length = u32::try_from(length)
+            .ok()
+            .filter(|length| length.checked_add(36).is_some())
+            .ok_or_else(|| Error::invalid("joined audio is too long for a WAV file"))?;
+        file.seek(SeekFrom::Start(0)).map_err(failed)?;
+        file.write_all(&spec.header(length)).map_err(failed)?;
+        file.flush().map_err(failed)?;
+        Ok(chapters)
+    }
+
+    /// The headings that decide which chapter `entry` is in.
+    fn chapter_headings<'a>(&self, entry: &'a ManifestEntry) -> &'a [String] {
+        &entry.heading_path[..entry.heading_path.len().min(self.chapter_level)]
+    }
+
+    /// The WAV file at `path`, converted by ffmpeg to `spec` if it is in
+    /// another format or layout.
+    fn decode(&self, path: &Path, spec: Option<WavSpec>) -> Result<Vec<u8>, Error> {
+        let is_wav = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("wav"));
+        if is_wav {
+            let bytes = fs::read(path).map_err(|error| Error::io(path, error))?;
+            let matches = WavSpec::parse(&bytes)
+                .is_ok_and(|(piece, _)| spec.is_none_or(|spec| spec == piece));
+            if matches {
+                return Ok(bytes);
+            }
+        }
+        let mut command = self.command();
+        command.arg("-i").arg(path);
+        if let Some(spec) = spec {
+            let codec = match (spec.format, spec.bits_per_sample) {
+                (3, 64) => "pcm_f64le",
+                (3, _) => "pcm_f32le",
+                (_, 8) => "pcm_u8",
+                (_, 24) => "pcm_s24le",
+                (_, 32) => "pcm_s32le",
+                _ => "pcm_s16le",
+            };
+            command
+                .args(["-ar", &spec.sample_rate.to_string()])
+                .args(["-ac", &spec.channels.to_string()])
+                .args(["-c:a", codec]);
+        }
+        command.args(["-f", "wav", "-"]);
+        run(command).map_err(|error| error.at(path.display()))
+    }
+
+    /// Encode the joined `wav` as `output`, adding the title and chapters.
+    /// `metadata` is a scratch path for the ffmetadata file ffmpeg reads.
+    fn encode(
+        &self,
+        wav: &Path,
+        metadata: &Path,
+        output: &Path,
+        container: Container,
+        chapters: &[Chapter],
+        title: &str,
+    ) -> Result<(), Error> {
+        let id3 = self.chapters == Some(ChapterFormat::Id3);
+        let mut command = self.command();
+        command.arg("-i").arg(wav);
+        if !id3 {
+            let embedded = match (container, self.chapters) {
+                (Container::M4b, Some(_)) => chapters,
+                _ => &[],
+            };
+            fs::write(metadata, ffmetadata(embedded, Some(title)))
+                .map_err(|error| Error::io(metadata, error))?;
+            command
+                .arg("-i")
+                .arg(metadata)
+                .args(["-map_metadata", "1", "-map_chapters", "1"]);
+        }
+        command.args(["-map", "0:a"]);
+        if id3 {
+            // The tag with the chapters is added below instead.
+            command.args(["-write_id3v2", "0"]);
+        }
+        command.arg(output);
+        let encoded = run(command);
+        let _ = fs::remove_file(metadata);
+        encoded?;
+
+        if id3 {
+            let mut tagged = id3_tag(chapters, Some(title))?;
+            tagged.extend(fs::read(output).map_err(|error| Error::io(output, error))?);
+            fs::write(output, tagged).map_err(|error| Error::io(output, error))?;
+        }
+        Ok(())
+    }
+
+    fn command(&self) -> Command {
+        let mut command = Command::new(&self.ffmpeg);
+        command.args(["-v", "error", "-nostdin", "-y"]);
+        command
+    }
+}
+
+/// Run ffmpeg, returning what it writes to standard output or failing with
+/// its standard error.
+fn run(mut command: Command) -> Result<Vec<u8>, Error> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command
+        .output()
+        .map_err(|error| Error::backend(&program, format!("cannot run it: {error}")))?;
+    match output.status.success() {
+        true => Ok(output.stdout),
+        false => Err(Error::backend(
+            program,
+            format!(
+                "failed ({}): {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Chunker, OutputDir};
+
+    #[test]
+    fn test_join_wav() {
+        let dir = std::env::temp_dir().join(format!("listnr-join-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let chunks = Chunker::new()
+            .limit(12)
+            .chunk("# Intro\n\nOne two. Three four.\n\n## Setup\n\nFive.");
+        let mut manifest = OutputDir::new(&dir).write("doc", &chunks).unwrap();
+
+        // A tenth of a second of 8 kHz mono audio per chunk.
+        let spec = WavSpec {
+            format: 1,
+            channels: 1,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+        };
+        manifest.chunks.iter_mut().for_each(|entry| {
+            let audio = Path::new(&entry.file).with_extension("wav");
+            let mut bytes = spec.header(1600);
+            bytes.extend([1; 1600]);
+            fs::write(dir.join(&audio), bytes).unwrap();
+            entry.audio = Some(audio.to_string_lossy().into_owned());
+        });
+        manifest.write(dir.join(MANIFEST_FILE)).unwrap();
+        let headings: Vec<_> = manifest
+            .chunks
+            .iter()
+            .map(|entry| entry.heading_path.join("/"))
+            .collect();
+        assert_eq!(headings, ["Intro", "Intro", "Intro", "Intro/Setup"]);
+
+        let joiner = Joiner::new()
+            .gap(Duration::from_millis(100))
+            .heading_gap(Duration::from_millis(200))
+            .chapters(Some(ChapterFormat::Ffmetadata));
+        let output = dir.join("doc.wav");
+        let chapters = joiner.join(&dir, &output).unwrap();
+        let millis = |millis| Duration::from_millis(millis);
+        assert_eq!(
+            chapters,
+            [
+                Chapter::new("Intro", millis(0), millis(700)),
+                Chapter::new("Setup", millis(700), millis(800)),
+            ]
+        );
+        let bytes = fs::read(&output).unwrap();
+        let (joined, data) = WavSpec::parse(&bytes).unwrap();
+        assert_eq!(joined, spec);
+        assert_eq!(joined.duration(data.len()), millis(800));
+        assert_eq!(data[1600..3200], [0; 1600]);
+        assert!(fs::read_to_string(dir.join("doc.ffmetadata"))
+            .unwrap()
+            .contains("START=700\nEND=800\ntitle=Setup"));
+
+        let chapters = joiner.chapter_level(1).join(&dir, &output).unwrap();
+        assert_eq!(chapters, [Chapter::new("Intro", millis(0), millis(800))]);
+        assert!(Joiner::new()
+            .chapters(Some(ChapterFormat::Id3))
+            .join(&dir, &output)
+            .is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
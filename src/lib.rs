@@ -0,0 +1,123 @@
+//! Prepare markdown documents for text-to-speech.
+//!
+//! A [`Chunker`] parses markdown, applies a [`SubstitutionTable`] of
+//! pronunciation fixes, optionally spells out numbers, dates and units with a
+//! [`Normalization`], reads code blocks according to a [`CodeBlockPolicy`]
+//! and packs the spoken text into [`Chunk`]s that fit a provider's request
+//! limit.
+//!
+//! ```
+//! use listnr_tools::{Chunker, SubstitutionTable, Unit};
+//!
+//! let substitutions: SubstitutionTable = [("TTS", "text to speech")].into_iter().collect();
+//! let chunker = Chunker::new()
+//!     .limit(40)
+//!     .unit(Unit::Chars)
+//!     .substitutions(substitutions);
+//!
+//! let chunks = chunker.chunk("# Intro\n\nTTS reads text aloud. It needs short requests.");
+//! let texts: Vec<&str> = chunks.iter().map(|chunk| chunk.text.as_str()).collect();
+//! assert_eq!(
+//!     texts,
+//!     ["Intro", "text to speech reads text aloud.", "It needs short requests."]
+//! );
+//! ```
+
+/// Implement `Display` and case-insensitive `FromStr` for a fieldless enum
+/// from the names of its variants, as they are written on the command line
+/// and in dictionaries.
+macro_rules! names {
+    ($type:ident, $what:literal, { $($variant:ident => $name:literal),+ $(,)? }) => {
+        impl $type {
+            /// Every value, in declaration order.
+            pub const ALL: &'static [$type] = &[$($type::$variant),+];
+
+            /// The name of the value.
+            pub fn name(self) -> &'static str {
+                match self {
+                    $($type::$variant => $name),+
+                }
+            }
+        }
+
+        impl std::fmt::Display for $type {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(self.name())
+            }
+        }
+
+        impl std::str::FromStr for $type {
+            type Err = String;
+
+            fn from_str(name: &str) -> Result<Self, String> {
+                $type::ALL
+                    .iter()
+                    .copied()
+                    .find(|value| value.name().eq_ignore_ascii_case(name))
+                    .ok_or_else(|| {
+                        let names: Vec<&str> =
+                            $type::ALL.iter().map(|value| value.name()).collect();
+                        format!(
+                            concat!("unknown ", $what, " {:?}, expected one of {}"),
+                            name,
+                            names.join(", ")
+                        )
+                    })
+            }
+        }
+    };
+}
+
+mod audio;
+mod backend;
+mod cache;
+mod chapter;
+mod chunk;
+mod code;
+mod config;
+mod error;
+mod front_matter;
+mod input;
+mod join;
+mod lint;
+mod listnr;
+mod normalize;
+mod output;
+mod render;
+mod report;
+mod segment;
+mod ssml;
+mod substitution;
+mod unit;
+
+pub use audio::AudioFormat;
+pub use backend::{synthesize_dir, AudioOrigin, Espeak, Limits, Piper, TextBackend, TtsBackend};
+pub use cache::AudioCache;
+pub use chapter::{ffmetadata, id3_tag, Chapter, ChapterFormat};
+pub use chunk::{Chunk, Chunker};
+pub use code::{CodeAction, CodeBlockPolicy, ThresholdUnit};
+pub use config::{cache_dir, config_dir};
+pub use error::Error;
+pub use front_matter::{ChunkingOptions, CodeOptions, FrontMatter, FRONT_MATTER_LAYER};
+pub use input::{expand, Input, Source, DEFAULT_GLOBS};
+pub use join::Joiner;
+pub use lint::{lint, suggested_rows, Finding, FindingKind};
+pub use listnr::{
+    Client, Credentials, ListnrBackend, RetryPolicy, VoiceSettings, API_KEY_VAR, BASE_URL_VAR,
+    CREDENTIALS_FILE, DEFAULT_BASE_URL,
+};
+pub use normalize::{Locale, Normalization};
+pub use output::{
+    BatchEntry, BatchManifest, Existing, FileNameTemplate, Manifest, ManifestEntry, OutputDir,
+    BATCH_MANIFEST_FILE, MANIFEST_FILE,
+};
+pub use render::{
+    FootnoteStyle, HtmlStyle, ImageStyle, LinkStyle, ListStyle, RenderOptions, TableStyle,
+};
+pub use report::{diff, Position, Report, RuleReport};
+pub use segment::ChunkConfig;
+pub use ssml::Markup;
+pub use substitution::{
+    Boundary, Case, Hit, Kind, Scope, Substitution, SubstitutionMode, SubstitutionTable,
+};
+pub use unit::Unit;
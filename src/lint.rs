@@ -0,0 +1,264 @@
+//! Finding tokens a TTS engine is likely to mispronounce.
+
+use crate::chunk;
+use crate::front_matter;
+use crate::render;
+use crate::ssml::Markup;
+use crate::substitution::{Scope, SubstitutionMode, SubstitutionTable};
+use comrak::nodes::NodeValue;
+use comrak::{parse_document, Arena};
+use regex::Regex;
+use serde::Serialize;
+use std::collections::HashSet;
+
+/// Why a token was flagged.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FindingKind {
+    /// An all-caps abbreviation such as "HTTP".
+    Acronym,
+    /// An identifier such as "getUserName" or "JavaScript".
+    CamelCase,
+    /// A version number such as "v1.2" or "2.10.3".
+    Version,
+    /// An operator or symbol such as "->" or "~".
+    Symbol,
+    Emoji,
+    /// A URL written out in the text.
+    Url,
+}
+
+impl FindingKind {
+    /// The name used in reports and suggested dictionary notes.
+    pub fn name(self) -> &'static str {
+        match self {
+            FindingKind::Acronym => "acronym",
+            FindingKind::CamelCase => "camelcase",
+            FindingKind::Version => "version",
+            FindingKind::Symbol => "symbol",
+            FindingKind::Emoji => "emoji",
+            FindingKind::Url => "url",
+        }
+    }
+}
+
+/// A flagged token and a replacement to consider for it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[non_exhaustive]
+pub struct Finding {
+    pub kind: FindingKind,
+    pub token: String,
+    /// One-based line of the token.
+    pub line: usize,
+    /// One-based column of the token, in characters.
+    pub column: usize,
+    pub suggestion: String,
+}
+
+/// Flag the tokens in the spoken text of a markdown document, meaning its
+/// prose, headings and inline code, that no rule of `substitutions` covers
+/// when applied as the chunker would in `mode`: everywhere in
+/// [`SubstitutionMode::Source`], and in each rule's scopes, narrowed to
+/// `scopes` if given, in [`SubstitutionMode::Tree`].
+pub fn lint(
+    content: &str,
+    substitutions: &SubstitutionTable,
+    mode: SubstitutionMode,
+    scopes: Option<&[Scope]>,
+) -> Vec<Finding> {
+    let tokens = Regex::new(concat!(
+        r"(?P<url>\bhttps?://[^\s<>()\[\]]*[^\s<>()\[\].,;:!?'`]|\bwww\.[^\s<>()\[\]]*[^\s<>()\[\].,;:!?'`])",
+        r"|(?P<version>\bv\d+(?:\.\d+)+\b|\b\d+\.\d+\.\d+\b)",
+        r"|(?P<emoji>[\x{1F000}-\x{1FAFF}\x{2600}-\x{27BF}]\x{FE0F}?)",
+        r"|(?P<camelcase>\b[a-z]+(?:[A-Z][a-z0-9]*)+\b|\b[A-Z][a-z0-9]+(?:[A-Z][a-z0-9]+)+\b)",
+        r"|(?P<acronym>\b[A-Z]{2,}s?\b)",
+        r"|(?P<symbol>->|=>|<-|<=|>=|!=|==|&&|\|\||~)",
+    ))
+    .expect("lint pattern is valid");
+    let kinds = [
+        ("url", FindingKind::Url),
+        ("version", FindingKind::Version),
+        ("emoji", FindingKind::Emoji),
+        ("camelcase", FindingKind::CamelCase),
+        ("acronym", FindingKind::Acronym),
+        ("symbol", FindingKind::Symbol),
+    ];
+
+    let front_lines = front_matter::split(content).0.matches('\n').count();
+    let arena = Arena::new();
+    let root = parse_document(&arena, content, &chunk::options(content));
+    let mut findings = Vec::new();
+    root.descendants().for_each(|node| {
+        let data = node.data.borrow();
+        let in_heading = node
+            .ancestors()
+            .any(|node| matches!(node.data.borrow().value, NodeValue::Heading(_)));
+        let (text, scope) = match data.value {
+            NodeValue::Text(ref text) if in_heading => (text, Scope::Heading),
+            NodeValue::Text(ref text) => (text, Scope::Prose),
+            NodeValue::Code(ref code) => (&code.literal, Scope::Code),
+            _ => return,
+        };
+        let hits = match mode {
+            SubstitutionMode::Source => substitutions.substitute(text, None, Markup::Text).1,
+            SubstitutionMode::Tree if scopes.is_some_and(|scopes| !scopes.contains(&scope)) => {
+                Vec::new()
+            }
+            SubstitutionMode::Tree => substitutions.substitute(text, Some(scope), Markup::Text).1,
+        };
+        let covered: Vec<(usize, usize)> = hits
+            .iter()
+            .filter(|hit| hit.line == 1)
+            .map(|hit| (hit.column, hit.column + hit.matched.chars().count()))
+            .collect();
+        let start = data.sourcepos.start;
+
+        tokens.captures_iter(text).for_each(|caps| {
+            let Some((kind, found)) = kinds
+                .iter()
+                .find_map(|(name, kind)| caps.name(name).map(|found| (*kind, found)))
+            else {
+                return;
+            };
+            let line = text[..found.start()].matches('\n').count();
+            let line_start = text[..found.start()].rfind('\n').map_or(0, |ii| ii + 1);
+            let column = text[line_start..found.start()].chars().count() + 1;
+            let end = column + found.as_str().chars().count();
+            if line == 0 && covered.iter().any(|&(from, to)| from < end && column < to) {
+                return;
+            }
+            findings.push(Finding {
+                kind,
+                token: found.as_str().to_string(),
+                line: start.line + front_lines + line,
+                column: match line {
+                    0 => start.column + column - 1,
+                    _ => column,
+                },
+                suggestion: suggest(kind, found.as_str()),
+            });
+        });
+    });
+    findings
+}
+
+/// A replacement that reads `token` aloud.
+fn suggest(kind: FindingKind, token: &str) -> String {
+    match kind {
+        FindingKind::Acronym => {
+            // "A P Is" would be read as the word "is".
+            let (letters, plural) = match token.strip_suffix('s') {
+                Some(letters) => (letters, "'s"),
+                None => (token, ""),
+            };
+            let spelled: Vec<String> = letters.chars().map(String::from).collect();
+            format!("{}{plural}", spelled.join(" "))
+        }
+        FindingKind::CamelCase => {
+            let mut words = String::new();
+            token.chars().enumerate().for_each(|(ii, ch)| {
+                if ii > 0 && ch.is_uppercase() {
+                    words.push(' ');
+                }
+                words.extend(ch.to_lowercase());
+            });
+            words
+        }
+        FindingKind::Version => {
+            let number = token.trim_start_matches('v').replace('.', " point ");
+            format!("version {number}")
+        }
+        FindingKind::Symbol => match token {
+            "->" => "to",
+            "=>" => "maps to",
+            "<-" => "from",
+            "<=" => "at most",
+            ">=" => "at least",
+            "!=" => "is not",
+            "==" => "equals",
+            "&&" => "and",
+            "||" => "or",
+            "~" => "about",
+            _ => "",
+        }
+        .to_string(),
+        FindingKind::Emoji => String::new(),
+        FindingKind::Url => format!("a link to {}", render::domain(token).replace('.', " dot ")),
+    }
+}
+
+/// Dictionary rows, with a header, suggesting a replacement for each flagged
+/// token once.
+pub fn suggested_rows(findings: &[Finding]) -> String {
+    let mut seen = HashSet::new();
+    let rows = findings
+        .iter()
+        .filter(|finding| seen.insert(&finding.token))
+        .map(|finding| {
+            [
+                finding.token.as_str(),
+                finding.suggestion.as_str(),
+                finding.kind.name(),
+            ]
+        });
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    std::iter::once(["pattern", "replacement", "note"])
+        .chain(rows)
+        .for_each(|row| writer.write_record(row).expect("writing to memory"));
+    String::from_utf8(writer.into_inner().expect("writing to memory")).expect("fields are UTF-8")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::substitution::Substitution;
+
+    #[test]
+    fn test_lint() {
+        let content = "# HTTP APIs\n\nCall `getUserName` in v2.1 -> done 🚀, see https://docs.example.com/x.\n\
+                       The SQL and JSON parts.\n";
+        let substitutions: SubstitutionTable = [("SQL", "sequel")].into_iter().collect();
+        let findings = lint(content, &substitutions, SubstitutionMode::Source, None);
+        let flagged: Vec<_> = findings
+            .iter()
+            .map(|finding| {
+                (
+                    finding.kind,
+                    finding.token.as_str(),
+                    finding.line,
+                    finding.column,
+                )
+            })
+            .collect();
+        assert_eq!(
+            flagged,
+            [
+                (FindingKind::Acronym, "HTTP", 1, 3),
+                (FindingKind::Acronym, "APIs", 1, 8),
+                (FindingKind::CamelCase, "getUserName", 3, 7),
+                (FindingKind::Version, "v2.1", 3, 23),
+                (FindingKind::Symbol, "->", 3, 28),
+                (FindingKind::Emoji, "🚀", 3, 36),
+                (FindingKind::Url, "https://docs.example.com/x", 3, 43),
+                (FindingKind::Acronym, "JSON", 4, 13),
+            ]
+        );
+        assert_eq!(
+            suggested_rows(&findings[..4]),
+            "pattern,replacement,note\nHTTP,H T T P,acronym\nAPIs,A P I's,acronym\n\
+             getUserName,get user name,camelcase\nv2.1,version 2 point 1,version\n"
+        );
+
+        let mut substitutions = SubstitutionTable::new();
+        substitutions.insert_rule(Substitution::new("JSON", "jason").scopes([Scope::Code]));
+        let flagged = |mode, scopes: Option<&[Scope]>| -> Vec<String> {
+            lint(content, &substitutions, mode, scopes)
+                .into_iter()
+                .filter(|finding| finding.token == "JSON")
+                .map(|finding| finding.token)
+                .collect()
+        };
+        assert!(flagged(SubstitutionMode::Source, None).is_empty());
+        assert_eq!(flagged(SubstitutionMode::Tree, None), ["JSON"]);
+    }
+}
@@ -0,0 +1,495 @@
+//! Client for the Listnr text-to-speech API.
+//!
+//! Synthesis is a job: the text is submitted to `{base_url}/convert-text`,
+//! its status polled at `{base_url}/status/{id}` until it is `completed` or
+//! `failed`, and the audio downloaded from the URL the finished job names.
+//! Requests that fail with a rate limit (429), a server error or a transport
+//! error are retried with exponential backoff, honouring `Retry-After`.
+//!
+//! Credentials are read from the environment or from the user's
+//! configuration directory, never from the command line, so that they do not
+//! end up in shell history or process listings.
+
+use crate::audio::AudioFormat;
+use crate::backend::{Limits, TtsBackend};
+use crate::chunk::Chunk;
+use crate::config::config_dir;
+use crate::error::Error;
+use crate::unit::Unit;
+use serde::Deserialize;
+use std::fmt;
+use std::io::Read;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+use ureq::ErrorKind;
+
+/// The API used unless the credentials name another.
+pub const DEFAULT_BASE_URL: &str = "https://bff.listnr.tech/api/tts/v1";
+
+/// Environment variable holding the API key.
+pub const API_KEY_VAR: &str = "LISTNR_API_KEY";
+
+/// Environment variable overriding the API base URL.
+pub const BASE_URL_VAR: &str = "LISTNR_API_URL";
+
+/// File in the configuration directory holding credentials, as a `[listnr]`
+/// table with `api_key` and optionally `base_url`.
+pub const CREDENTIALS_FILE: &str = "credentials.toml";
+
+/// How to reach and authenticate with the API.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Credentials {
+    api_key: String,
+    base_url: String,
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("api_key", &"<redacted>")
+            .field("base_url", &self.base_url)
+            .finish()
+    }
+}
+
+#[derive(Deserialize)]
+struct CredentialsFile {
+    listnr: Option<CredentialsTable>,
+}
+
+#[derive(Deserialize)]
+struct CredentialsTable {
+    api_key: Option<String>,
+    base_url: Option<String>,
+}
+
+impl Credentials {
+    /// Credentials for `base_url`, with any trailing slash removed.
+    pub fn new(api_key: impl Into<String>, base_url: impl Into<String>) -> Self {
+        Credentials {
+            api_key: api_key.into(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Read [`API_KEY_VAR`] and [`BASE_URL_VAR`], falling back to
+    /// [`CREDENTIALS_FILE`] in [`config_dir`] for whatever they leave unset.
+    pub fn load() -> Result<Self, Error> {
+        let file = match config_dir().map(|dir| dir.join(CREDENTIALS_FILE)) {
+            Some(path) if path.exists() => Some(Self::read_table(&path)?),
+            _ => None,
+        };
+        let from_file = |field: fn(&CredentialsTable) -> &Option<String>| {
+            file.as_ref().and_then(|table| field(table).clone())
+        };
+        let api_key = std::env::var(API_KEY_VAR)
+            .ok()
+            .filter(|key| !key.is_empty())
+            .or_else(|| from_file(|table| &table.api_key))
+            .ok_or_else(|| {
+                Error::invalid(format!(
+                    "no Listnr API key: set {API_KEY_VAR} or api_key in {CREDENTIALS_FILE}"
+                ))
+            })?;
+        let base_url = std::env::var(BASE_URL_VAR)
+            .ok()
+            .filter(|url| !url.is_empty())
+            .or_else(|| from_file(|table| &table.base_url))
+            .unwrap_or_else(|| DEFAULT_BASE_URL.to_string());
+        Ok(Credentials::new(api_key, base_url))
+    }
+
+    fn read_table(path: &Path) -> Result<CredentialsTable, Error> {
+        let toml = std::fs::read_to_string(path).map_err(|error| Error::io(path, error))?;
+        let file: CredentialsFile =
+            toml::from_str(&toml).map_err(|error| Error::parse(path.display(), error))?;
+        Ok(file.listnr.unwrap_or(CredentialsTable {
+            api_key: None,
+            base_url: None,
+        }))
+    }
+}
+
+/// How a chunk is spoken.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct VoiceSettings {
+    pub voice: String,
+    pub language: String,
+    /// Speaking rate, where 1.0 is normal speed.
+    pub speed: f32,
+    pub format: AudioFormat,
+}
+
+impl VoiceSettings {
+    /// Speak with `voice` in US English at normal speed, as MP3.
+    pub fn new(voice: impl Into<String>) -> Self {
+        VoiceSettings {
+            voice: voice.into(),
+            language: "en-US".to_string(),
+            speed: 1.0,
+            format: AudioFormat::default(),
+        }
+    }
+}
+
+/// How often and how patiently failed requests are retried.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RetryPolicy {
+    /// Attempts per request, including the first.
+    pub attempts: u32,
+    /// Wait before the first retry, doubled for each one after.
+    pub initial_backoff: Duration,
+    /// Longest wait between attempts, including any `Retry-After`.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+/// A synthesis job as the API reports it.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Job {
+    id: String,
+    status: String,
+    audio_url: Option<String>,
+    error: Option<String>,
+}
+
+/// A blocking Listnr API client.
+#[derive(Clone, Debug)]
+pub struct Client {
+    agent: ureq::Agent,
+    credentials: Credentials,
+    retry: RetryPolicy,
+    poll_interval: Duration,
+    poll_timeout: Duration,
+}
+
+impl Client {
+    /// A client for the API named by `credentials`, with the default retry
+    /// policy and polling.
+    pub fn new(credentials: Credentials) -> Self {
+        Client {
+            agent: ureq::AgentBuilder::new()
+                .timeout(Duration::from_secs(60))
+                .build(),
+            credentials,
+            retry: RetryPolicy::default(),
+            poll_interval: Duration::from_secs(2),
+            poll_timeout: Duration::from_secs(600),
+        }
+    }
+
+    /// How failed requests are retried.
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Wait between status checks of a job.
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Give up on a job that has not finished after `timeout`.
+    pub fn poll_timeout(mut self, timeout: Duration) -> Self {
+        self.poll_timeout = timeout;
+        self
+    }
+
+    /// Speak `text`, sent as SSML if it is a `<speak>` document, and return
+    /// the audio.
+    pub fn synthesize(&self, text: &str, settings: &VoiceSettings) -> Result<Vec<u8>, Error> {
+        let input = match text.trim_start().starts_with("<speak") {
+            true => "ssml",
+            false => "text",
+        };
+        let mut body = serde_json::json!({
+            "voice": settings.voice,
+            "language": settings.language,
+            "speed": settings.speed,
+            "audioFormat": settings.format.extension(),
+        });
+        body[input] = text.into();
+        let url = format!("{}/convert-text", self.credentials.base_url);
+        let mut job: Job = self
+            .send(|| self.authorized(self.agent.post(&url)), Some(&body))?
+            .into_json()
+            .map_err(failed)?;
+
+        let started = Instant::now();
+        let audio_url = loop {
+            match job.status.as_str() {
+                "completed" => {
+                    break job
+                        .audio_url
+                        .ok_or_else(|| failed(format!("job {} completed without audio", job.id)))?
+                }
+                "failed" => {
+                    let reason = job.error.unwrap_or_else(|| "no reason given".to_string());
+                    return Err(failed(format!("job {} failed: {reason}", job.id)));
+                }
+                _ if started.elapsed() >= self.poll_timeout => {
+                    return Err(failed(format!("job {} did not finish in time", job.id)))
+                }
+                _ => {
+                    thread::sleep(self.poll_interval);
+                    let url = format!("{}/status/{}", self.credentials.base_url, job.id);
+                    job = self
+                        .send(|| self.authorized(self.agent.get(&url)), None)?
+                        .into_json()
+                        .map_err(failed)?;
+                }
+            }
+        };
+
+        let mut audio = Vec::new();
+        self.send(|| self.agent.get(&audio_url), None)?
+            .into_reader()
+            .read_to_end(&mut audio)
+            .map_err(failed)?;
+        Ok(audio)
+    }
+
+    fn authorized(&self, request: ureq::Request) -> ureq::Request {
+        request.set("x-listnr-token", &self.credentials.api_key)
+    }
+
+    /// Make a request, with `body` as JSON if there is one, retrying rate
+    /// limits and failures to connect. Requests without a body are also
+    /// retried on server and transport errors; one with a body starts a job,
+    /// and may have done so before the error, so it is not.
+    fn send(
+        &self,
+        request: impl Fn() -> ureq::Request,
+        body: Option<&serde_json::Value>,
+    ) -> Result<ureq::Response, Error> {
+        let mut backoff = self.retry.initial_backoff;
+        let mut attempt = 1;
+        loop {
+            let retry = attempt < self.retry.attempts;
+            let response = match body {
+                Some(body) => request().send_json(body),
+                None => request().call(),
+            };
+            let wait = match response {
+                Ok(response) => return Ok(response),
+                Err(ureq::Error::Status(429, response)) if retry => response
+                    .header("Retry-After")
+                    .and_then(|seconds| seconds.trim().parse().ok())
+                    .map_or(backoff, Duration::from_secs),
+                Err(ureq::Error::Transport(error))
+                    if retry
+                        && matches!(error.kind(), ErrorKind::Dns | ErrorKind::ConnectionFailed) =>
+                {
+                    backoff
+                }
+                Err(ureq::Error::Status(500..=599, _) | ureq::Error::Transport(_))
+                    if retry && body.is_none() =>
+                {
+                    backoff
+                }
+                Err(ureq::Error::Status(status, response)) => {
+                    let url = response.get_url().to_string();
+                    let body = response.into_string().unwrap_or_default();
+                    return Err(failed(format!("{url}: status {status}: {}", body.trim())));
+                }
+                Err(error) => return Err(failed(error)),
+            };
+            thread::sleep(wait.min(self.retry.max_backoff));
+            backoff = (backoff * 2).min(self.retry.max_backoff);
+            attempt += 1;
+        }
+    }
+}
+
+/// An error from the Listnr API or the connection to it.
+fn failed(message: impl fmt::Display) -> Error {
+    Error::backend("listnr", message)
+}
+
+/// The Listnr API as a [`TtsBackend`], speaking every chunk with the same
+/// settings.
+#[derive(Clone, Debug)]
+pub struct ListnrBackend {
+    client: Client,
+    settings: VoiceSettings,
+}
+
+impl ListnrBackend {
+    /// Listnr rejects requests over 1500 characters.
+    pub const LIMITS: Limits = Limits::new(Some(1500), Unit::Chars, true);
+
+    /// Speak every chunk with `settings` through `client`.
+    pub fn new(client: Client, settings: VoiceSettings) -> Self {
+        ListnrBackend { client, settings }
+    }
+}
+
+impl TtsBackend for ListnrBackend {
+    fn name(&self) -> &str {
+        "listnr"
+    }
+
+    fn limits(&self) -> Limits {
+        Self::LIMITS
+    }
+
+    fn settings(&self) -> String {
+        let settings = &self.settings;
+        format!(
+            "listnr voice={} language={} speed={} format={}",
+            settings.voice,
+            settings.language,
+            settings.speed,
+            settings.format.extension()
+        )
+    }
+
+    fn extension(&self) -> &str {
+        self.settings.format.extension()
+    }
+
+    fn synthesize(&self, chunk: &Chunk, path: &Path) -> Result<(), Error> {
+        let audio = self.client.synthesize(&chunk.text, &self.settings)?;
+        std::fs::write(path, audio).map_err(|error| Error::io(path, error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    /// Answer one connection per response, in order, and return the request
+    /// lines received. Each response is built from the server's base URL.
+    fn serve(
+        responses: impl FnOnce(&str) -> Vec<(u16, &'static str, Vec<u8>)>,
+    ) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let responses = responses(&base_url);
+        let handle = thread::spawn(move || {
+            responses
+                .into_iter()
+                .map(|(status, headers, body)| {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut request_line = String::new();
+                    reader.read_line(&mut request_line).unwrap();
+                    let mut length = 0;
+                    loop {
+                        let mut header = String::new();
+                        reader.read_line(&mut header).unwrap();
+                        if header.trim().is_empty() {
+                            break;
+                        }
+                        if let Some((name, value)) = header.split_once(':') {
+                            if name.eq_ignore_ascii_case("content-length") {
+                                length = value.trim().parse().unwrap();
+                            }
+                        }
+                    }
+                    let mut request_body = vec![0; length];
+                    reader.read_exact(&mut request_body).unwrap();
+                    write!(
+                        stream,
+                        "HTTP/1.1 {status} X\r\n{headers}Content-Length: {}\r\n\
+                         Connection: close\r\n\r\n",
+                        body.len()
+                    )
+                    .unwrap();
+                    stream.write_all(&body).unwrap();
+                    request_line.trim().to_string()
+                })
+                .collect()
+        });
+        (base_url, handle)
+    }
+
+    fn client(base_url: &str) -> Client {
+        let retry = RetryPolicy {
+            attempts: 3,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(5),
+        };
+        Client::new(Credentials::new("secret", base_url))
+            .retry(retry)
+            .poll_interval(Duration::from_millis(1))
+    }
+
+    #[test]
+    fn test_synthesize() {
+        let (base_url, server) = serve(|base_url| {
+            let completed = format!(
+                r#"{{"id": "j1", "status": "completed", "audioUrl": "{base_url}/audio/j1.mp3"}}"#
+            );
+            vec![
+                (429, "Retry-After: 0\r\n", b"slow down".to_vec()),
+                (200, "", br#"{"id": "j1", "status": "queued"}"#.to_vec()),
+                (503, "", Vec::new()),
+                (200, "", br#"{"id": "j1", "status": "processing"}"#.to_vec()),
+                (200, "", completed.into_bytes()),
+                (200, "", b"ID3 audio".to_vec()),
+            ]
+        });
+        let audio = client(&base_url)
+            .synthesize("Hello.", &VoiceSettings::new("amber"))
+            .unwrap();
+        assert_eq!(audio, b"ID3 audio");
+        assert_eq!(
+            server.join().unwrap(),
+            [
+                "POST /convert-text HTTP/1.1",
+                "POST /convert-text HTTP/1.1",
+                "GET /status/j1 HTTP/1.1",
+                "GET /status/j1 HTTP/1.1",
+                "GET /status/j1 HTTP/1.1",
+                "GET /audio/j1.mp3 HTTP/1.1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_synthesize_errors() {
+        let (base_url, server) = serve(|_| vec![(401, "", b"bad token".to_vec())]);
+        let error = client(&base_url)
+            .synthesize("Hello.", &VoiceSettings::new("amber"))
+            .unwrap_err();
+        assert!(error.to_string().ends_with("status 401: bad token"));
+        assert_eq!(server.join().unwrap().len(), 1);
+
+        let (base_url, server) = serve(|_| {
+            vec![(
+                200,
+                "",
+                br#"{"id": "j2", "status": "failed", "error": "bad voice"}"#.to_vec(),
+            )]
+        });
+        let error = client(&base_url)
+            .synthesize("Hello.", &VoiceSettings::new("amber"))
+            .unwrap_err();
+        assert_eq!(error.to_string(), "listnr: job j2 failed: bad voice");
+        server.join().unwrap();
+
+        let (base_url, server) = serve(|_| vec![(503, "", b"busy".to_vec())]);
+        let error = client(&base_url)
+            .synthesize("Hello.", &VoiceSettings::new("amber"))
+            .unwrap_err();
+        assert!(error.to_string().ends_with("status 503: busy"));
+        assert_eq!(server.join().unwrap(), ["POST /convert-text HTTP/1.1"]);
+    }
+}
@@ -1,153 +1,951 @@
-use clap::Parser;
-use comrak::nodes::NodeCodeBlock;
-use comrak::{nodes::NodeValue, parse_document, Arena, ComrakOptions};
-use csv::Reader;
-use std::collections::HashMap;
+use clap::{Args as ClapArgs, Parser, Subcommand, ValueEnum};
+use listnr_tools::{
+    config_dir, diff, expand, lint, suggested_rows, synthesize_dir, AudioCache, AudioFormat,
+    AudioOrigin, BatchEntry, BatchManifest, ChapterFormat, Chunk, Chunker, Client, CodeAction,
+    CodeBlockPolicy, Credentials, Espeak, Existing, FileNameTemplate, FootnoteStyle, FrontMatter,
+    HtmlStyle, ImageStyle, Input, Joiner, Limits, LinkStyle, ListStyle, ListnrBackend, Locale,
+    ManifestEntry, Markup, Normalization, OutputDir, Piper, RenderOptions, Report, RetryPolicy,
+    Scope, Source, SubstitutionMode, SubstitutionTable, TableStyle, TextBackend, ThresholdUnit,
+    TtsBackend, Unit, VoiceSettings, BATCH_MANIFEST_FILE, MANIFEST_FILE,
+};
+use serde::Serialize;
 use std::error::Error;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(subcommand_negates_reqs = true, args_conflicts_with_subcommands = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Input markdown files or directories of them; - reads standard input
+    #[arg(required_unless_present = "input")]
+    inputs: Vec<PathBuf>,
+
+    /// Input markdown file; may be repeated
+    #[arg(short, long)]
+    input: Vec<PathBuf>,
+
+    /// Only read files in input directories whose relative path matches this
+    /// glob; may be repeated [default: *.md, *.markdown]
+    #[arg(long)]
+    glob: Vec<String>,
+
+    /// Stop at the first document that fails instead of skipping it
+    #[arg(long)]
+    fail_fast: bool,
+
+    #[command(flatten)]
+    dictionaries: DictionaryArgs,
+
+    /// Print each substitution made, and the dictionary it came from, to stderr
+    #[arg(long)]
+    explain: bool,
+
+    /// Report each substitution rule's hits, including rules that never fired
+    #[arg(long, value_enum)]
+    report: Option<ReportFormat>,
+
+    /// Write the report to this file instead of stderr
+    #[arg(long, requires = "report")]
+    report_file: Option<PathBuf>,
+
+    /// Print a diff of the substitutions instead of the chunks, writing no files
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Spell out numbers, dates, times, units, currency and percentages,
+    /// or only the given kinds
+    #[arg(long, value_enum, value_delimiter = ',', num_args = 0..=1, default_missing_value = "all")]
+    normalize: Option<Vec<NormalizeKind>>,
+
+    /// Language and conventions of --normalize
+    #[arg(long, value_enum, default_value_t = Locale::default(), requires = "normalize")]
+    locale: Locale,
+
+    /// Fit chunks to this backend's request limits and SSML support
+    #[arg(long, value_enum)]
+    backend: Option<BackendKind>,
+
+    /// Maximum length of a chunk, measured in --unit, up to the backend's
+    /// limit [default: the backend's limit, or 1500]
+    #[arg(short, long, value_parser = parse_limit)]
+    limit: Option<usize>,
+
+    /// Unit in which chunk length is measured, which must be the backend's
+    /// if it has a limit [default: the backend's unit, or chars]
+    #[arg(short, long, value_enum)]
+    unit: Option<Unit>,
+
+    /// Output format for the chunks
+    #[arg(short, long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+
+    /// Do not count SSML tags against the limit
+    #[arg(long)]
+    exclude_markup: bool,
+
+    /// Also start chunks at points chosen by their content, so that editing
+    /// a document only changes the chunks near the edit and the audio cache
+    /// can reuse the rest
+    #[arg(long)]
+    stable_boundaries: bool,
+
+    /// Start a new chunk at every heading of this level or above
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=6))]
+    section_level: Option<u8>,
+
+    /// Length above which a code block counts as long, in --code-threshold-unit
+    #[arg(long, default_value_t = CodeBlockPolicy::default().threshold)]
+    code_threshold: usize,
+
+    /// Unit of --code-threshold
+    #[arg(long, value_enum, default_value_t = ThresholdUnit::default())]
+    code_threshold_unit: ThresholdUnit,
+
+    /// What to read for code blocks up to the threshold
+    #[arg(long, value_enum, default_value_t = CodeBlockPolicy::default().short)]
+    code_short: CodeAction,
+
+    /// What to read for code blocks over the threshold
+    #[arg(long, value_enum, default_value_t = CodeBlockPolicy::default().long)]
+    code_long: CodeAction,
+
+    /// Action for one fenced language, as LANGUAGE=ACTION; may be repeated
+    #[arg(long, value_parser = parse_code_language)]
+    code_language: Vec<(String, CodeAction)>,
+
+    /// Only read code blocks in these languages; may be repeated
+    #[arg(long)]
+    code_allow: Vec<String>,
+
+    /// Never read code blocks in these languages, e.g. mermaid; may be repeated
+    #[arg(long)]
+    code_deny: Vec<String>,
+
+    /// Introduce code that is read out with its language
+    #[arg(long)]
+    announce_language: bool,
+
+    /// How list items are introduced
+    #[arg(long, value_enum, default_value_t = ListStyle::default())]
+    lists: ListStyle,
+
+    /// How links are read
+    #[arg(long, value_enum, default_value_t = LinkStyle::default())]
+    links: LinkStyle,
+
+    /// How images are read
+    #[arg(long, value_enum, default_value_t = ImageStyle::default())]
+    images: ImageStyle,
+
+    /// How tables are read
+    #[arg(long, value_enum, default_value_t = TableStyle::default())]
+    tables: TableStyle,
+
+    /// How footnotes are read
+    #[arg(long, value_enum, default_value_t = FootnoteStyle::default())]
+    footnotes: FootnoteStyle,
+
+    /// How raw HTML blocks are read
+    #[arg(long, value_enum, default_value_t = HtmlStyle::default())]
+    html: HtmlStyle,
+
+    /// Write each chunk to its own file in this directory, plus a manifest;
+    /// in a batch, each document gets a subdirectory and batch.json lists them
+    #[arg(short, long)]
+    output_dir: Option<PathBuf>,
+
+    /// Chunk file name; {stem} is the input file stem, {index} the one-based chunk number
+    #[arg(long, default_value = "{index:04}.txt", requires = "output_dir")]
+    name_template: String,
+
+    /// What to do with files left in the output directory by an earlier run
+    #[arg(long, value_enum, default_value_t = Existing::default(), requires = "output_dir")]
+    existing: Existing,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Flag tokens likely to be mispronounced that no substitution covers,
+    /// and suggest dictionary rows for them
+    Lint(LintArgs),
+    /// Speak the chunk files written by --output-dir and save the audio next
+    /// to them, reusing cached audio for chunks spoken before. The Listnr API
+    /// key is read from LISTNR_API_KEY or credentials.toml in the
+    /// configuration directory
+    Synthesize(SynthesizeArgs),
+    /// Join the audio of one document's chunks, once synthesized, into a
+    /// single file with chapter markers from its headings. MP3 and M4B
+    /// output is encoded with ffmpeg
+    Join(JoinArgs),
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum BackendKind {
+    /// The Listnr API
+    Listnr,
+    /// espeak-ng, run locally
+    Espeak,
+    /// piper, run locally with --model
+    Piper,
+    /// Write the text each chunk would send, without speaking it
+    Text,
+}
+
+impl BackendKind {
+    fn limits(self) -> Limits {
+        match self {
+            BackendKind::Listnr => ListnrBackend::LIMITS,
+            BackendKind::Espeak => Espeak::LIMITS,
+            BackendKind::Piper => Piper::LIMITS,
+            BackendKind::Text => TextBackend::LIMITS,
+        }
+    }
+}
+
+#[derive(ClapArgs, Debug)]
+struct SynthesizeArgs {
+    /// Output directory of an earlier run, holding a manifest or batch.json
+    dir: PathBuf,
+
+    /// What to speak the chunks with
+    #[arg(long, value_enum, default_value_t = BackendKind::Listnr)]
+    backend: BackendKind,
+
+    /// Voice to speak with; required for Listnr
+    #[arg(long)]
+    voice: Option<String>,
+
+    /// Voice model for piper, an .onnx file
+    #[arg(long)]
+    model: Option<PathBuf>,
+
+    /// Executable to run for espeak-ng or piper, instead of the one on PATH
+    #[arg(long)]
+    program: Option<PathBuf>,
+
+    /// Language of the text, for Listnr
+    #[arg(long, default_value = "en-US")]
+    language: String,
+
+    /// Speaking rate, where 1.0 is normal speed
+    #[arg(long, default_value_t = 1.0, value_parser = parse_speed)]
+    speed: f32,
+
+    /// Format of the audio files, for Listnr; local engines write WAV
+    #[arg(long, value_enum, default_value_t = AudioFormat::default())]
+    audio_format: AudioFormat,
+
+    /// Attempts per Listnr request before giving up
+    #[arg(long, default_value_t = RetryPolicy::default().attempts)]
+    attempts: u32,
+
+    /// Seconds between checks on a Listnr synthesis job
+    #[arg(long, default_value = "2", value_parser = parse_seconds)]
+    poll_interval: Duration,
+
+    /// Directory of cached audio, reused for chunks whose text and voice
+    /// settings have not changed [default: $XDG_CACHE_HOME/listnr-tools/audio]
+    #[arg(long)]
+    cache_dir: Option<PathBuf>,
+
+    /// Speak every chunk, neither reading nor filling the cache
+    #[arg(long, conflicts_with = "cache_dir")]
+    no_cache: bool,
+}
+
+#[derive(ClapArgs, Debug)]
+struct JoinArgs {
+    /// Directory of one document's chunk files, holding its manifest
+    dir: PathBuf,
+
+    /// File to write: .wav, .mp3 or .m4b
+    #[arg(short, long)]
+    output: PathBuf,
+
+    /// Seconds of silence between chunks
+    #[arg(long, default_value = "0.5", value_parser = parse_seconds)]
+    gap: Duration,
+
+    /// Seconds of silence before a chunk under a new heading
+    #[arg(long, default_value = "1.5", value_parser = parse_seconds)]
+    heading_gap: Duration,
+
+    /// Deepest heading level that starts a chapter
+    #[arg(long, default_value_t = 6)]
+    chapter_level: usize,
+
+    /// How to write chapter markers [default: id3 for MP3, otherwise
+    /// ffmetadata]
+    #[arg(long, value_enum)]
+    chapters: Option<ChapterFormat>,
+
+    /// Write no chapter markers
+    #[arg(long, conflicts_with = "chapters")]
+    no_chapters: bool,
+
+    /// Title of the recording [default: the output file stem]
+    #[arg(long)]
+    title: Option<String>,
+
+    /// Executable to run for ffmpeg, instead of the one on PATH
+    #[arg(long)]
+    ffmpeg: Option<PathBuf>,
+}
+
+#[derive(ClapArgs, Debug)]
+struct LintArgs {
     /// Input markdown file
     #[arg(short, long)]
     input: PathBuf,
 
-    /// Optional CSV file for word substitution
+    #[command(flatten)]
+    dictionaries: DictionaryArgs,
+
+    /// Output format for the findings
+    #[arg(short, long, value_enum, default_value_t = LintFormat::Text)]
+    format: LintFormat,
+}
+
+/// Where pronunciations come from, and how they are applied.
+#[derive(ClapArgs, Debug)]
+struct DictionaryArgs {
+    /// CSV pronunciation dictionary; may be repeated, and later files
+    /// override earlier ones
     #[arg(short, long)]
-    substitutions: Option<PathBuf>,
+    substitutions: Vec<PathBuf>,
+
+    /// Do not load the dictionaries in $XDG_CONFIG_HOME/listnr-tools first
+    #[arg(long)]
+    no_default_dictionaries: bool,
+
+    /// Ignore pronunciations and chunking options in the document's front
+    /// matter, which otherwise override the command line
+    #[arg(long)]
+    ignore_front_matter: bool,
+
+    /// Whether substitutions rewrite the markdown source or the parsed text
+    #[arg(long, value_enum, default_value_t = SubstitutionMode::default())]
+    substitution_mode: SubstitutionMode,
+
+    /// Only substitute in these kinds of text, with --substitution-mode tree
+    #[arg(long, value_enum, value_delimiter = ',')]
+    substitution_scope: Vec<Scope>,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum LintFormat {
+    /// Findings followed by suggested dictionary rows
+    Text,
+    /// A JSON array of findings
+    Json,
+    /// Only the suggested dictionary rows
+    Csv,
 }
 
-#[cfg(not(test))]
-const SECTION_CHAR_LIMIT: usize = 1500;
-#[cfg(test)]
-const SECTION_CHAR_LIMIT: usize = 10;
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Format {
+    /// Chunks separated by a line showing their length
+    Text,
+    /// A JSON array of chunk objects
+    Json,
+    /// One JSON chunk object per line
+    Jsonl,
+    /// Chunks as SSML documents, separated like text
+    Ssml,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum NormalizeKind {
+    All,
+    Numbers,
+    Ordinals,
+    Dates,
+    Times,
+    Units,
+    Currency,
+    Percentages,
+}
 
-fn main() -> Result<(), Box<dyn Error>> {
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum ReportFormat {
+    Text,
+    Json,
+}
+
+fn main() -> Result<ExitCode, Box<dyn Error>> {
     let args = Args::parse();
+    match &args.command {
+        Some(Command::Lint(lint_args)) => return lint_document(lint_args),
+        Some(Command::Synthesize(synthesize_args)) => {
+            return synthesize(synthesize_args).map(|()| ExitCode::SUCCESS)
+        }
+        Some(Command::Join(join_args)) => return join(join_args).map(|()| ExitCode::SUCCESS),
+        None => {}
+    }
+
+    let paths: Vec<PathBuf> = args.input.iter().chain(&args.inputs).cloned().collect();
+    let inputs = expand(&paths, &args.glob)?;
+    // Several documents, or a directory of them, each get their own chunk set.
+    let batch = inputs.len() > 1 || paths.iter().any(|path| path.is_dir());
+    let chunker = chunker(&args)?;
+    let output_dir = match &args.output_dir {
+        Some(path) => Some(
+            OutputDir::new(path)
+                .template(FileNameTemplate::new(&args.name_template)?)
+                .existing(args.existing)
+                .stable_boundaries(args.stable_boundaries),
+        ),
+        None => None,
+    };
+    if let (true, Some(output_dir)) = (batch && !args.dry_run, &output_dir) {
+        output_dir.prepare_batch()?;
+    }
 
-    // Read the input markdown file
-    let mut content = fs::read_to_string(&args.input)?;
+    let mut manifest = BatchManifest::default();
+    let mut reports = Vec::new();
+    let mut documents = Vec::new();
+    let mut failed = 0;
+    for input in &inputs {
+        let result = process(&args, &chunker, input, batch, output_dir.as_ref());
+        let (chunks, error) = match result {
+            Ok(document) => {
+                reports.extend(document.report.map(|report| (input.label(), report)));
+                let printed = batch && output_dir.is_none() && !args.dry_run;
+                if let (Format::Json, true) = (args.format, printed) {
+                    documents.push(BatchDocument {
+                        input: input.label(),
+                        chunks: document.chunks,
+                    });
+                }
+                (document.entries, None)
+            }
+            Err(error) if batch && !args.fail_fast => {
+                eprintln!("error: {}: {error}", input.label());
+                failed += 1;
+                (Vec::new(), Some(error.to_string()))
+            }
+            Err(error) => {
+                if let (true, Some(output_dir)) = (batch, &output_dir) {
+                    output_dir.write_batch(&manifest)?;
+                }
+                return Err(format!("{}: {error}", input.label()).into());
+            }
+        };
+        manifest.documents.push(BatchEntry {
+            input: match &input.source {
+                Source::Stdin => "-".to_string(),
+                Source::File(path) => path.display().to_string(),
+            },
+            directory: input.name.clone(),
+            chunks,
+            error,
+        });
+    }
 
-    // Process substitutions if CSV file is provided
-    if let Some(csv_path) = args.substitutions {
-        let substitutions = read_substitutions(&csv_path)?;
-        content = apply_substitutions(&content, &substitutions);
+    if let (true, false, Some(output_dir)) = (batch, args.dry_run, &output_dir) {
+        output_dir.write_batch(&manifest)?;
+    }
+    if !documents.is_empty() {
+        println!("{}", serde_json::to_string_pretty(&documents)?);
+    }
+    if let Some(format) = args.report {
+        write_reports(&reports, format, batch, args.report_file.as_deref())?;
+    }
+    if failed > 0 {
+        eprintln!("{failed} of {} documents failed", inputs.len());
+        return Ok(ExitCode::FAILURE);
     }
+    Ok(ExitCode::SUCCESS)
+}
 
-    let chunks = chunk_markdown(&content);
+/// The chunker for the command line options, before any front matter.
+fn chunker(args: &Args) -> Result<Chunker, Box<dyn Error>> {
+    let markup = match args.format {
+        Format::Ssml => Markup::Ssml,
+        Format::Text | Format::Json | Format::Jsonl => Markup::Text,
+    };
+    let mut chunker = Chunker::new().markup(markup);
+    if let Some(backend) = args.backend {
+        let limits = backend.limits();
+        if let Some(max_length) = limits.max_length {
+            if let Some(unit) = args.unit.filter(|&unit| unit != limits.unit) {
+                return Err(format!(
+                    "--unit {unit} does not match the backend, which counts {}",
+                    limits.unit
+                )
+                .into());
+            }
+            if let Some(limit) = args.limit.filter(|&limit| limit > max_length) {
+                return Err(format!(
+                    "--limit {limit} is over the backend's limit of {max_length} {}",
+                    limits.unit
+                )
+                .into());
+            }
+        }
+        chunker = chunker.limits(&limits);
+    }
+    if let Some(limit) = args.limit {
+        chunker = chunker.limit(limit);
+    }
+    if let Some(unit) = args.unit {
+        chunker = chunker.unit(unit);
+    }
+    chunker = chunker
+        .count_markup(!args.exclude_markup)
+        .stable_boundaries(args.stable_boundaries)
+        .section_level(args.section_level)
+        .render(render_options(args))
+        .code_blocks(code_block_policy(args))
+        .normalization(normalization(args))
+        .substitutions(load_substitutions(&args.dictionaries)?)
+        .substitution_mode(args.dictionaries.substitution_mode);
+    if !args.dictionaries.substitution_scope.is_empty() {
+        chunker = chunker.substitution_scopes(args.dictionaries.substitution_scope.iter().copied());
+    }
+    Ok(chunker)
+}
 
-    // Print the chunks
-    chunks.iter().for_each(|chunk| {
-        println!("\n--- --- --- {} --- --- ---\n{}", chunk.len(), chunk);
-    });
+/// What came of chunking one document.
+struct Document {
+    chunks: Vec<Chunk>,
+    /// The files written for the chunks, if any.
+    entries: Vec<ManifestEntry>,
+    report: Option<Report>,
+}
 
-    Ok(())
+/// A document's chunks in batch JSON output.
+#[derive(Serialize)]
+struct BatchDocument {
+    input: String,
+    chunks: Vec<Chunk>,
 }
 
-fn read_substitutions(path: &PathBuf) -> Result<HashMap<String, String>, Box<dyn Error>> {
-    let mut rdr = Reader::from_path(path)?;
-    let mut substitutions = HashMap::new();
+/// A chunk in batch JSONL output, labelled with its document.
+#[derive(Serialize)]
+struct BatchChunk<'a> {
+    input: &'a str,
+    #[serde(flatten)]
+    chunk: &'a Chunk,
+}
 
-    rdr.records().try_for_each(|result| {
-        let record = result?;
-        if record.len() == 2 {
-            substitutions.insert(record[0].to_string(), record[1].to_string());
+/// Chunk one document and print, diff or write its chunks. In a batch, each
+/// document is written to its own subdirectory and printed under its name.
+fn process(
+    args: &Args,
+    chunker: &Chunker,
+    input: &Input,
+    batch: bool,
+    output_dir: Option<&OutputDir>,
+) -> Result<Document, Box<dyn Error>> {
+    let content = input.read()?;
+    let mut chunker = chunker.clone();
+    if !args.dictionaries.ignore_front_matter {
+        if let Some(front_matter) = FrontMatter::from_document(&content)? {
+            chunker = chunker.front_matter(&front_matter);
         }
-        Ok::<(), csv::Error>(())
-    })?;
+    }
+
+    let (chunks, hits) = chunker.chunk_with_hits(&content);
+    if args.explain {
+        hits.iter().for_each(|hit| {
+            eprintln!(
+                "{}:{}:{}: {:?} -> {:?} ({})",
+                input.label(),
+                hit.line,
+                hit.column,
+                hit.matched,
+                hit.replacement,
+                hit.layer.as_deref().unwrap_or("built in")
+            )
+        });
+    }
+    let report = args
+        .report
+        .map(|_| Report::new(chunker.substitution_table(), &hits));
+
+    let mut entries = Vec::new();
+    match output_dir {
+        _ if args.dry_run => print!("{}", diff(&input.label(), &content, &hits)),
+        Some(output_dir) if batch => {
+            entries = output_dir
+                .document(&input.name)
+                .write(input.stem(), &chunks)?
+                .chunks;
+        }
+        Some(output_dir) => entries = output_dir.write(input.stem(), &chunks)?.chunks,
+        None if batch => print_batch_chunks(input, &chunks, args.format)?,
+        None => print_chunks(&chunks, args.format)?,
+    }
+    Ok(Document {
+        chunks,
+        entries,
+        report,
+    })
+}
+
+/// Write the substitution reports, each under its document's name in a batch.
+fn write_reports(
+    reports: &[(String, Report)],
+    format: ReportFormat,
+    batch: bool,
+    path: Option<&Path>,
+) -> Result<(), Box<dyn Error>> {
+    let report = match (format, batch) {
+        (ReportFormat::Text, false) => reports
+            .iter()
+            .map(|(_, report)| report.to_string())
+            .collect(),
+        (ReportFormat::Text, true) => reports
+            .iter()
+            .map(|(input, report)| format!("==> {input} <==\n{report}"))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        (ReportFormat::Json, false) => match reports.first() {
+            Some((_, report)) => serde_json::to_string_pretty(report)? + "\n",
+            None => String::new(),
+        },
+        (ReportFormat::Json, true) => {
+            let reports: serde_json::Map<String, serde_json::Value> = reports
+                .iter()
+                .map(|(input, report)| Ok((input.clone(), serde_json::to_value(report)?)))
+                .collect::<Result<_, serde_json::Error>>()?;
+            serde_json::to_string_pretty(&reports)? + "\n"
+        }
+    };
+    match path {
+        Some(path) => fs::write(path, report)?,
+        None => eprint!("{report}"),
+    }
+    Ok(())
+}
 
+/// Layer the default dictionaries and then each given file, printing any
+/// warnings.
+fn load_substitutions(args: &DictionaryArgs) -> Result<SubstitutionTable, Box<dyn Error>> {
+    let mut substitutions = match config_dir() {
+        Some(dir) if !args.no_default_dictionaries => SubstitutionTable::from_dir(dir)?,
+        _ => SubstitutionTable::new(),
+    };
+    args.substitutions.iter().try_for_each(|path| {
+        substitutions.merge(SubstitutionTable::from_path(path)?);
+        Ok::<(), Box<dyn Error>>(())
+    })?;
+    substitutions
+        .warnings()
+        .iter()
+        .for_each(|warning| eprintln!("warning: {warning}"));
     Ok(substitutions)
 }
 
-fn apply_substitutions(content: &str, substitutions: &HashMap<String, String>) -> String {
-    let mut result = content.to_string();
-    substitutions.iter().for_each(|(from, to)| {
-        result = result.replace(from, to);
-    });
-    result
+/// Print the findings of the lint subcommand, failing with status 1 if there
+/// are any.
+fn lint_document(args: &LintArgs) -> Result<ExitCode, Box<dyn Error>> {
+    let content = fs::read_to_string(&args.input)?;
+    let mut substitutions = load_substitutions(&args.dictionaries)?;
+    if !args.dictionaries.ignore_front_matter {
+        let front_matter = FrontMatter::from_document(&content)
+            .map_err(|error| format!("{}: {error}", args.input.display()))?;
+        if let Some(front_matter) = front_matter {
+            substitutions.merge(front_matter.substitutions);
+        }
+    }
+
+    let dictionaries = &args.dictionaries;
+    let scopes =
+        Some(dictionaries.substitution_scope.as_slice()).filter(|scopes| !scopes.is_empty());
+    let findings = lint(
+        &content,
+        &substitutions,
+        dictionaries.substitution_mode,
+        scopes,
+    );
+    match args.format {
+        LintFormat::Text => {
+            findings.iter().for_each(|finding| {
+                println!(
+                    "{}:{}:{}: {} {:?}",
+                    args.input.display(),
+                    finding.line,
+                    finding.column,
+                    finding.kind.name(),
+                    finding.token
+                )
+            });
+            if !findings.is_empty() {
+                print!(
+                    "\nSuggested dictionary rows:\n{}",
+                    suggested_rows(&findings)
+                );
+            }
+        }
+        LintFormat::Json => println!("{}", serde_json::to_string_pretty(&findings)?),
+        LintFormat::Csv => print!("{}", suggested_rows(&findings)),
+    }
+    match findings.is_empty() {
+        true => Ok(ExitCode::SUCCESS),
+        false => Ok(ExitCode::FAILURE),
+    }
 }
 
-fn process_code_blocks(code_block: &NodeCodeBlock) -> String {
-    if code_block.literal.len() > 80 {
-        "listing omitted".to_string()
+/// Synthesize every chunk of the documents in an output directory,
+/// recording the audio files in their manifests.
+fn synthesize(args: &SynthesizeArgs) -> Result<(), Box<dyn Error>> {
+    let backend = backend(args)?;
+    let cache = match (args.no_cache, &args.cache_dir) {
+        (true, _) => None,
+        (false, Some(dir)) => Some(AudioCache::new(dir)),
+        (false, None) => AudioCache::user(),
+    };
+    let (mut synthesized, mut cached) = (0, 0);
+    let mut progress = |entry: &ManifestEntry, origin: AudioOrigin| {
+        let note = match origin {
+            AudioOrigin::Synthesized => {
+                synthesized += 1;
+                ""
+            }
+            AudioOrigin::Cached => {
+                cached += 1;
+                " (cached)"
+            }
+        };
+        eprintln!(
+            "{} -> {}{note}",
+            entry.file,
+            entry.audio.as_deref().unwrap_or_default()
+        )
+    };
+
+    let batch_path = args.dir.join(BATCH_MANIFEST_FILE);
+    let mut stable = true;
+    if batch_path.exists() {
+        let mut batch = BatchManifest::read(&batch_path)?;
+        for index in 0..batch.documents.len() {
+            if batch.documents[index].error.is_some() {
+                continue;
+            }
+            let dir = args.dir.join(&batch.documents[index].directory);
+            let manifest = synthesize_dir(backend.as_ref(), &dir, cache.as_ref(), &mut progress)?;
+            stable &= manifest.stable_boundaries;
+            batch.documents[index].chunks = manifest.chunks;
+            OutputDir::new(&args.dir).write_batch(&batch)?;
+        }
     } else {
-        code_block.literal.to_string()
-    }
-}
-
-fn chunk_markdown(content: &str) -> Vec<String> {
-    let arena = Arena::new();
-    let root = parse_document(&arena, content, &ComrakOptions::default());
-
-    root.descendants()
-        .filter_map(|node| match node.data.borrow().value {
-            NodeValue::Text(ref text) => Some(text.clone()),
-            NodeValue::Code(ref node_code) => Some(node_code.literal.clone()),
-            NodeValue::CodeBlock(ref code_block) => Some(process_code_blocks(code_block)),
-            _ => None,
-        })
-        .fold(Vec::new(), |mut chunks, text| {
-            if let Some(last_chunk) = chunks.last_mut() {
-                if last_chunk.len() + text.len() + 1 <= SECTION_CHAR_LIMIT {
-                    last_chunk.push(' ');
-                    last_chunk.push_str(&text);
-                } else {
-                    chunks.push(text);
-                }
-            } else {
-                chunks.push(text);
+        let manifest = synthesize_dir(backend.as_ref(), &args.dir, cache.as_ref(), &mut progress)?;
+        stable = manifest.stable_boundaries;
+    }
+    eprintln!("{synthesized} chunks synthesized, {cached} from the cache");
+    if cache.is_some() && !stable {
+        eprintln!(
+            "warning: the chunks were written without --stable-boundaries, so after an edit \
+             every later chunk may change and miss the cache"
+        );
+    }
+    Ok(())
+}
+
+/// Join a document's chunk audio into one file, listing its chapters.
+fn join(args: &JoinArgs) -> Result<(), Box<dyn Error>> {
+    if !args.dir.join(MANIFEST_FILE).exists() && args.dir.join(BATCH_MANIFEST_FILE).exists() {
+        return Err(format!(
+            "{} holds a batch; join one document's directory within it",
+            args.dir.display()
+        )
+        .into());
+    }
+    let mp3 = args
+        .output
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("mp3"));
+    let chapters = match (args.no_chapters, args.chapters, mp3) {
+        (true, _, _) => None,
+        (false, Some(chapters), _) => Some(chapters),
+        (false, None, true) => Some(ChapterFormat::Id3),
+        (false, None, false) => Some(ChapterFormat::Ffmetadata),
+    };
+    let mut joiner = Joiner::new()
+        .gap(args.gap)
+        .heading_gap(args.heading_gap)
+        .chapter_level(args.chapter_level)
+        .chapters(chapters)
+        .title(args.title.clone());
+    if let Some(ffmpeg) = &args.ffmpeg {
+        joiner = joiner.ffmpeg(ffmpeg);
+    }
+    joiner
+        .join(&args.dir, &args.output)?
+        .iter()
+        .for_each(|chapter| {
+            let seconds = chapter.start.as_secs();
+            eprintln!(
+                "{}:{:02}:{:02} {}",
+                seconds / 3600,
+                seconds / 60 % 60,
+                seconds % 60,
+                chapter.title
+            )
+        });
+    Ok(())
+}
+
+fn backend(args: &SynthesizeArgs) -> Result<Box<dyn TtsBackend>, Box<dyn Error>> {
+    Ok(match args.backend {
+        BackendKind::Listnr => {
+            let voice = args
+                .voice
+                .as_ref()
+                .ok_or("--voice is required for Listnr")?;
+            let mut retry = RetryPolicy::default();
+            retry.attempts = args.attempts.max(1);
+            let client = Client::new(Credentials::load()?)
+                .retry(retry)
+                .poll_interval(args.poll_interval);
+            let mut settings = VoiceSettings::new(voice);
+            settings.language = args.language.clone();
+            settings.speed = args.speed;
+            settings.format = args.audio_format;
+            Box::new(ListnrBackend::new(client, settings))
+        }
+        BackendKind::Espeak => {
+            let mut espeak = Espeak::new().voice(args.voice.clone()).speed(args.speed);
+            if let Some(program) = &args.program {
+                espeak = espeak.program(program);
             }
-            chunks
-        })
+            Box::new(espeak)
+        }
+        BackendKind::Piper => {
+            let model = args.model.as_ref().ok_or("--model is required for piper")?;
+            let mut piper = Piper::new(model).speed(args.speed);
+            if let Some(program) = &args.program {
+                piper = piper.program(program);
+            }
+            Box::new(piper)
+        }
+        BackendKind::Text => Box::new(TextBackend),
+    })
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+fn render_options(args: &Args) -> RenderOptions {
+    let mut render = RenderOptions::default();
+    render.lists = args.lists;
+    render.links = args.links;
+    render.images = args.images;
+    render.tables = args.tables;
+    render.footnotes = args.footnotes;
+    render.html = args.html;
+    render
+}
 
-    #[test]
-    fn test_chunk_markdown() {
-        let content = r#"This is a test of the chunking function.
-It should split the content into chunks of SECTION_CHAR_LIMIT characters or less."#;
-        let chunks = chunk_markdown(content);
+fn normalization(args: &Args) -> Option<Normalization> {
+    let kinds = args.normalize.as_ref()?;
+    let enabled = |kind| kinds.contains(&NormalizeKind::All) || kinds.contains(&kind);
+    let mut normalization = Normalization::new(args.locale);
+    normalization.numbers = enabled(NormalizeKind::Numbers);
+    normalization.ordinals = enabled(NormalizeKind::Ordinals);
+    normalization.dates = enabled(NormalizeKind::Dates);
+    normalization.times = enabled(NormalizeKind::Times);
+    normalization.units = enabled(NormalizeKind::Units);
+    normalization.currency = enabled(NormalizeKind::Currency);
+    normalization.percentages = enabled(NormalizeKind::Percentages);
+    Some(normalization)
+}
 
-        // Filter out single-word chunks that exceed SECTION_CHAR_LIMIT
-        let filtered_chunks: Vec<&String> = chunks
+fn code_block_policy(args: &Args) -> CodeBlockPolicy {
+    let lowercase = |languages: &[String]| {
+        languages
             .iter()
-            .filter(|chunk| chunk.len() <= SECTION_CHAR_LIMIT)
-            .collect();
+            .map(|language| language.to_lowercase())
+            .collect()
+    };
+    let mut policy = CodeBlockPolicy::default();
+    policy.threshold = args.code_threshold;
+    policy.threshold_unit = args.code_threshold_unit;
+    policy.short = args.code_short;
+    policy.long = args.code_long;
+    policy.announce_language = args.announce_language;
+    policy.languages = args.code_language.iter().cloned().collect();
+    policy.allow = lowercase(&args.code_allow);
+    policy.deny = lowercase(&args.code_deny);
+    policy
+}
 
-        println!("Total chunks: {}", chunks.len());
-        println!("Filtered chunks: {}", filtered_chunks.len());
+fn parse_code_language(value: &str) -> Result<(String, CodeAction), String> {
+    let (language, action) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected LANGUAGE=ACTION, got {value:?}"))?;
+    Ok((language.to_lowercase(), action.parse()?))
+}
 
-        filtered_chunks.iter().enumerate().for_each(|(ii, chunk)| {
-            println!("Chunk {}: '{}' (length: {})", ii, chunk, chunk.len());
-        });
+/// A length of time in seconds, such as `0.5`.
+fn parse_seconds(value: &str) -> Result<Duration, String> {
+    let seconds: f64 = value
+        .parse()
+        .map_err(|error: std::num::ParseFloatError| error.to_string())?;
+    Duration::try_from_secs_f64(seconds)
+        .map_err(|_| "expected a finite number of seconds, not below zero".to_string())
+}
 
-        // Calculate expected number of chunks
-        let total_length: usize = content
-            .split_whitespace()
-            .map(|word| word.len())
-            .sum::<usize>()
-            + content.split_whitespace().count()
-            - 1; // Add spaces between words
-        let expected_chunks = (total_length as f64 / SECTION_CHAR_LIMIT as f64).ceil() as usize;
-
-        assert!(
-            filtered_chunks.len() <= expected_chunks,
-            "Too many chunks. Expected: >= {}, Actual: {}",
-            expected_chunks,
-            filtered_chunks.len()
-        );
+/// A speaking rate, which must be above zero.
+fn parse_speed(value: &str) -> Result<f32, String> {
+    let speed: f32 = value
+        .parse()
+        .map_err(|error: std::num::ParseFloatError| error.to_string())?;
+    match speed.is_finite() && speed > 0.0 {
+        true => Ok(speed),
+        false => Err("expected a finite speed above zero".to_string()),
+    }
+}
 
-        filtered_chunks.iter().for_each(|chunk| {
-            assert!(
-                chunk.len() <= SECTION_CHAR_LIMIT,
-                "Filtered chunk exceeds SECTION_CHAR_LIMIT: '{}'",
-                chunk
-            );
-        });
+/// Print one document's chunks in a batch: under a header as text, or
+/// labelled with the document in JSONL. JSON is printed once for the batch.
+fn print_batch_chunks(
+    input: &Input,
+    chunks: &[Chunk],
+    format: Format,
+) -> Result<(), Box<dyn Error>> {
+    match format {
+        Format::Text | Format::Ssml => {
+            println!("==> {} <==", input.label());
+            print_chunks(chunks, format)?;
+            println!();
+        }
+        Format::Json => {}
+        Format::Jsonl => chunks.iter().try_for_each(|chunk| {
+            let chunk = BatchChunk {
+                input: &input.label(),
+                chunk,
+            };
+            println!("{}", serde_json::to_string(&chunk)?);
+            Ok::<(), serde_json::Error>(())
+        })?,
+    }
+    Ok(())
+}
+
+fn print_chunks(chunks: &[Chunk], format: Format) -> Result<(), Box<dyn Error>> {
+    match format {
+        Format::Text | Format::Ssml => chunks.iter().for_each(|chunk| {
+            println!("\n--- --- --- {} --- --- ---\n{}", chunk.length, chunk.text);
+        }),
+        Format::Json => println!("{}", serde_json::to_string_pretty(chunks)?),
+        Format::Jsonl => chunks.iter().try_for_each(|chunk| {
+            println!("{}", serde_json::to_string(chunk)?);
+            Ok::<(), serde_json::Error>(())
+        })?,
+    }
+    Ok(())
+}
+
+/// A chunk limit, which must leave room for at least one character.
+fn parse_limit(value: &str) -> Result<usize, String> {
+    match value.parse() {
+        Ok(limit) if limit > 0 => Ok(limit),
+        _ => Err("expected a whole number above zero".to_string()),
     }
 }
@@ -0,0 +1,454 @@
+//! Expansion of numbers, dates, times, units, currency and percentages into
+//! words, so that every TTS engine reads them the same way.
+//!
+//! Tokens are recognised in a locale-independent way, with `,` as the
+//! thousands separator and `.` as the decimal point, and spelled out by the
+//! [`Language`] of the chosen [`Locale`]. Tokens joined to letters or to
+//! other numbers, as in `v2`, `1.2.3` or `10-20`, are left as written. A
+//! minus sign directly before a number is read as "minus".
+
+mod en;
+
+use regex::{Captures, Regex};
+use std::ops::Range;
+use std::sync::OnceLock;
+
+/// The language and conventions numbers are spoken in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Locale {
+    /// American English: "September nineteenth, twenty twenty-four".
+    #[default]
+    EnUs,
+    /// British English: "the nineteenth of September twenty twenty-four".
+    EnGb,
+}
+
+names!(Locale, "locale", {
+    EnUs => "en-us",
+    EnGb => "en-gb",
+});
+
+impl Locale {
+    fn language(self) -> &'static dyn Language {
+        match self {
+            Locale::EnUs => &en::English::US,
+            Locale::EnGb => &en::English::GB,
+        }
+    }
+}
+
+/// Which kinds of token to spell out, and in which locale. Kinds that are
+/// turned off are left as written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Normalization {
+    pub locale: Locale,
+    /// Whole and decimal numbers: "1,500" is "one thousand five hundred".
+    /// Four-digit numbers from 1100 to 2099 are read as years, so "1999" is
+    /// "nineteen ninety-nine".
+    pub numbers: bool,
+    /// "21st" is "twenty-first".
+    pub ordinals: bool,
+    /// ISO dates such as "2024-09-19", and numeric dates such as
+    /// "9/19/2024" in the locale's order. Dates that do not exist, such as
+    /// "2024-02-30", are left as written.
+    pub dates: bool,
+    /// "14:30" and "9:30 am".
+    pub times: bool,
+    /// Numbers with a unit: "5ms" is "five milliseconds", "10x" "ten times".
+    pub units: bool,
+    /// "$3.2M" is "three point two million dollars".
+    pub currency: bool,
+    /// "15%" is "fifteen percent".
+    pub percentages: bool,
+}
+
+impl Default for Normalization {
+    fn default() -> Self {
+        Normalization {
+            locale: Locale::default(),
+            numbers: true,
+            ordinals: true,
+            dates: true,
+            times: true,
+            units: true,
+            currency: true,
+            percentages: true,
+        }
+    }
+}
+
+impl Normalization {
+    /// Every kind of token, spelled out in `locale`.
+    pub fn new(locale: Locale) -> Self {
+        Normalization {
+            locale,
+            ..Normalization::default()
+        }
+    }
+
+    /// Spell out the enabled kinds of token in `text`.
+    pub fn apply(&self, text: &str) -> String {
+        let language = self.locale.language();
+        let mut normalized = String::with_capacity(text.len());
+        let mut last = 0;
+        tokens().captures_iter(text).for_each(|captures| {
+            let token = captures.get(0).unwrap();
+            if !is_isolated(text, token.range()) {
+                return;
+            }
+            let Some(words) = self.expand(language, &captures) else {
+                return;
+            };
+            normalized.push_str(&text[last..token.start()]);
+            normalized.push_str(&words);
+            last = token.end();
+        });
+        normalized.push_str(&text[last..]);
+        normalized
+    }
+
+    /// Words for one token, or `None` to leave it as written.
+    fn expand(&self, language: &dyn Language, captures: &Captures) -> Option<String> {
+        let group = |name| captures.name(name).map(|found| found.as_str());
+        let signed = |words: String| match group("minus") {
+            Some(_) => format!("{} {words}", language.minus()),
+            None => words,
+        };
+        // Dates, times and ordinals are never negative: keep the dash.
+        let unsigned = |words: String| format!("{}{words}", group("minus").unwrap_or_default());
+        if captures.name("date").is_some() {
+            let (year, month, day) = match group("year") {
+                Some(year) => (year, group("month")?, group("day")?),
+                None => {
+                    let (month, day) = language.month_day(group("first")?, group("second")?);
+                    (group("numeric_year")?, month, day)
+                }
+            };
+            let (year, month, day) = (year.parse().ok()?, month.parse().ok()?, day.parse().ok()?);
+            let valid =
+                (1..=12).contains(&month) && (1..=days_in_month(year, month)).contains(&day);
+            (self.dates && valid).then(|| unsigned(language.date(year, month, day)))
+        } else if captures.name("time").is_some() {
+            let hour: u64 = group("hour")?.parse().ok()?;
+            let minute: u64 = group("minute")?.parse().ok()?;
+            let meridiem = group("meridiem").map(|meridiem| match meridiem {
+                "a" | "A" => Meridiem::Am,
+                _ => Meridiem::Pm,
+            });
+            let valid = minute < 60
+                && match meridiem {
+                    Some(_) => (1..=12).contains(&hour),
+                    None => hour < 24,
+                };
+            (self.times && valid).then(|| unsigned(language.time(hour, minute, meridiem)))
+        } else if captures.name("currency").is_some() {
+            let symbol = group("symbol")?.chars().next()?;
+            let amount = Number::parse(group("amount")?)?;
+            self.currency
+                .then(|| currency(language, symbol, &amount, group("scale")))
+                .flatten()
+                .map(signed)
+        } else if captures.name("percentage").is_some() {
+            let value = Number::parse(group("percentage_value")?)?;
+            self.percentages
+                .then(|| signed(format!("{} {}", value.words(language), language.percent())))
+        } else if captures.name("ordinal").is_some() {
+            let value = group("ordinal_value")?.parse().ok()?;
+            self.ordinals.then(|| unsigned(language.ordinal(value)))
+        } else if captures.name("unit").is_some() {
+            let value = Number::parse(group("unit_value")?)?;
+            let symbol = group("spaced").or(group("attached"))?;
+            let (singular, plural) = language.unit(symbol)?;
+            let name = if value.is_one() { singular } else { plural };
+            self.units
+                .then(|| signed(format!("{} {name}", value.words(language))))
+        } else {
+            let number = group("number")?;
+            let value = Number::parse(number)?;
+            let year = group("minus").is_none()
+                && number.len() == 4
+                && (1100..=2099).contains(&value.integer);
+            self.numbers.then(|| match year {
+                true => language.year(value.integer),
+                false => signed(value.words(language)),
+            })
+        }
+    }
+}
+
+/// Words a locale speaks numbers, dates, times and units in. Adding a locale
+/// means implementing this trait in a submodule and adding a [`Locale`]
+/// variant for it.
+trait Language: Sync {
+    /// "twenty-one".
+    fn cardinal(&self, number: u64) -> String;
+    /// "twenty-first".
+    fn ordinal(&self, number: u64) -> String;
+    /// The word for the decimal point.
+    fn point(&self) -> &'static str;
+    /// The word for a minus sign.
+    fn minus(&self) -> &'static str;
+    /// "twenty twenty-four".
+    fn year(&self, year: u64) -> String;
+    fn date(&self, year: u64, month: u64, day: u64) -> String;
+    /// The month and day of a numeric date written as `first/second/year`.
+    fn month_day<'a>(&self, first: &'a str, second: &'a str) -> (&'a str, &'a str);
+    fn time(&self, hour: u64, minute: u64, meridiem: Option<Meridiem>) -> String;
+    /// Singular and plural names of a unit symbol.
+    fn unit(&self, symbol: &str) -> Option<(&'static str, &'static str)>;
+    fn currency(&self, symbol: char) -> Option<Currency>;
+    /// The number named by a scale suffix such as "M" or "million".
+    fn scale(&self, suffix: &str) -> Option<&'static str>;
+    /// The word joining major and minor currency units.
+    fn and(&self) -> &'static str;
+    fn percent(&self) -> &'static str;
+}
+
+/// Whether a time is before or after noon.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Meridiem {
+    Am,
+    Pm,
+}
+
+/// Singular and plural names of a currency and of its hundredth part.
+struct Currency {
+    major: (&'static str, &'static str),
+    minor: Option<(&'static str, &'static str)>,
+}
+
+/// A number as written, without thousands separators.
+struct Number<'a> {
+    integer: u64,
+    /// Digits after the decimal point.
+    fraction: &'a str,
+}
+
+impl<'a> Number<'a> {
+    /// `None` for numbers too large to spell out.
+    fn parse(text: &'a str) -> Option<Self> {
+        let (integer, fraction) = text.split_once('.').unwrap_or((text, ""));
+        Some(Number {
+            integer: integer.replace(',', "").parse().ok()?,
+            fraction,
+        })
+    }
+
+    fn is_one(&self) -> bool {
+        self.integer == 1 && self.fraction.is_empty()
+    }
+
+    /// "three point one four".
+    fn words(&self, language: &dyn Language) -> String {
+        let integer = language.cardinal(self.integer);
+        if self.fraction.is_empty() {
+            return integer;
+        }
+        let digits: Vec<String> = self
+            .fraction
+            .chars()
+            .filter_map(|digit| digit.to_digit(10))
+            .map(|digit| language.cardinal(digit.into()))
+            .collect();
+        format!("{integer} {} {}", language.point(), digits.join(" "))
+    }
+}
+
+/// "three dollars and fifty cents", or "three point two million dollars"
+/// with a scale.
+fn currency(
+    language: &dyn Language,
+    symbol: char,
+    amount: &Number,
+    scale: Option<&str>,
+) -> Option<String> {
+    let Currency { major, minor } = language.currency(symbol)?;
+    let name = |value: u64, (singular, plural): (&str, &str)| match value {
+        1 => format!("{} {singular}", language.cardinal(value)),
+        _ => format!("{} {plural}", language.cardinal(value)),
+    };
+    if let Some(scale) = scale {
+        let scale = language.scale(scale)?;
+        return Some(format!("{} {scale} {}", amount.words(language), major.1));
+    }
+    match (minor, amount.fraction.len()) {
+        (Some(minor), 2) => {
+            let cents: u64 = amount.fraction.parse().ok()?;
+            Some(match (amount.integer, cents) {
+                (0, _) => name(cents, minor),
+                (_, 0) => name(amount.integer, major),
+                (_, _) => format!(
+                    "{} {} {}",
+                    name(amount.integer, major),
+                    language.and(),
+                    name(cents, minor)
+                ),
+            })
+        }
+        _ if amount.is_one() => Some(format!("{} {}", amount.words(language), major.0)),
+        _ => Some(format!("{} {}", amount.words(language), major.1)),
+    }
+}
+
+/// Days in `month` of `year` in the Gregorian calendar.
+fn days_in_month(year: u64, month: u64) -> u64 {
+    match month {
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => {
+            29
+        }
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Unit symbols that may follow a number after a space.
+const SPACED_UNITS: &[&str] = &[
+    "ns", "µs", "μs", "us", "ms", "sec", "min", "hr", "hrs", "kB", "KB", "KiB", "MB", "MiB", "GB",
+    "GiB", "TB", "TiB", "PB", "kbps", "Kbps", "Mbps", "Gbps", "fps", "rpm", "Hz", "kHz", "MHz",
+    "GHz", "mm", "cm", "km", "km/h", "mph", "kg", "mg", "lb", "lbs", "°C", "°F", "px", "dpi",
+];
+
+/// Unit symbols too short to tell from a word unless joined to the number.
+/// Seconds, days and grams are left out, as "1s", "3d" and "5g" are more
+/// often plurals, dimensions and network generations.
+const ATTACHED_UNITS: &[&str] = &["h", "x"];
+
+/// Tokens to spell out, in order of precedence, after an optional minus
+/// sign. Each kind has a named group, and numbers are written `NUMBER`
+/// before expansion.
+fn tokens() -> &'static Regex {
+    static TOKENS: OnceLock<Regex> = OnceLock::new();
+    TOKENS.get_or_init(|| {
+        let alternation = |units: &[&str]| {
+            let mut units: Vec<&str> = units.to_vec();
+            units.sort_by_key(|unit| std::cmp::Reverse(unit.len()));
+            units
+                .iter()
+                .map(|unit| regex::escape(unit))
+                .collect::<Vec<_>>()
+                .join("|")
+        };
+        let pattern = r"(?x)
+            (?P<minus>-)?
+            (?:(?P<date>
+                \b(?P<year>\d{4})-(?P<month>\d{2})-(?P<day>\d{2})\b
+                | \b(?P<first>\d{1,2})/(?P<second>\d{1,2})/(?P<numeric_year>\d{4})\b)
+            | (?P<time>\b(?P<hour>\d{1,2}):(?P<minute>\d{2})\b
+                (?:\s?(?P<meridiem>[AaPp])\.?[Mm]\b)?)
+            | (?P<currency>(?P<symbol>[$€£¥])(?P<amount>NUMBER)
+                (?:\s?(?P<scale>thousand|million|billion|trillion|bn|[KkMBT])\b)?)
+            | (?P<percentage>\b(?P<percentage_value>NUMBER)\s?%)
+            | (?P<ordinal>\b(?P<ordinal_value>\d+)(?:st|nd|rd|th)\b)
+            | (?P<unit>\b(?P<unit_value>NUMBER)
+                (?:\s?(?P<spaced>SPACED)|(?P<attached>ATTACHED))\b)
+            | \b(?P<number>NUMBER)\b)"
+            .replace("NUMBER", r"(?:\d{1,3}(?:,\d{3})+|\d+)(?:\.\d+)?")
+            .replace("SPACED", &alternation(SPACED_UNITS))
+            .replace("ATTACHED", &alternation(ATTACHED_UNITS));
+        Regex::new(&pattern).unwrap()
+    })
+}
+
+/// Whether the token at `range` stands apart from the words and numbers
+/// around it, rather than being part of an identifier, version or range.
+fn is_isolated(text: &str, range: Range<usize>) -> bool {
+    let joined = |next: Option<char>, beyond: Option<char>| match next {
+        Some(next) if next.is_alphanumeric() || next == '_' => true,
+        Some('.' | ',' | ':' | '/' | '-') => beyond.is_some_and(char::is_alphanumeric),
+        _ => false,
+    };
+    let mut before = text[..range.start].chars().rev();
+    let mut after = text[range.end..].chars();
+    !joined(before.next(), before.next()) && !joined(after.next(), after.next())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalize(text: &str) -> String {
+        Normalization::default().apply(text)
+    }
+
+    #[test]
+    fn test_numbers() {
+        assert_eq!(
+            normalize("1,500 users, 3.14 and 21st."),
+            "one thousand five hundred users, three point one four and twenty-first."
+        );
+        assert_eq!(
+            normalize("See v2, 1.2.3, 10-20 and 0x1F."),
+            "See v2, 1.2.3, 10-20 and 0x1F."
+        );
+        assert_eq!(
+            normalize("Back in 1999, 2048 of 12,000 were -5, -2.5% and -3 °C."),
+            "Back in nineteen ninety-nine, twenty forty-eight of twelve thousand were \
+             minus five, minus two point five percent and minus three degrees Celsius."
+        );
+        assert_eq!(
+            normalize("In 1066 and 2100."),
+            "In one thousand sixty-six and two thousand one hundred."
+        );
+    }
+
+    #[test]
+    fn test_dates_and_times() {
+        assert_eq!(
+            normalize("On 2024-09-19 at 14:30, or 9/19/2024 at 9:05 am."),
+            "On September nineteenth, twenty twenty-four at two thirty PM, \
+             or September nineteenth, twenty twenty-four at nine oh five AM."
+        );
+        assert_eq!(
+            normalize("At 12:00 on 2024-13-01."),
+            "At twelve o'clock on 2024-13-01."
+        );
+        assert_eq!(
+            normalize("Not 2024-02-30 or 2023-02-29, but 2024-02-29."),
+            "Not 2024-02-30 or 2023-02-29, but February twenty-ninth, twenty twenty-four."
+        );
+    }
+
+    #[test]
+    fn test_units_currency_percentages() {
+        assert_eq!(
+            normalize("It takes 5ms, 1 GB and is 10x faster."),
+            "It takes five milliseconds, one gigabyte and is ten times faster."
+        );
+        assert_eq!(
+            normalize("1s and 0s; 3d models on 5g phones, charged in 2h."),
+            "1s and 0s; 3d models on 5g phones, charged in two hours."
+        );
+        assert_eq!(
+            normalize("Raised $3.2M, priced at $3.50 or €1, up 15%."),
+            "Raised three point two million dollars, priced at three dollars and fifty cents \
+             or one euro, up fifteen percent."
+        );
+    }
+
+    #[test]
+    fn test_locale() {
+        let british = Normalization::new(Locale::EnGb);
+        assert_eq!(
+            british.apply("On 19/9/2024 at 14:30, 105 km cost £2.05, 5% more."),
+            "On the nineteenth of September twenty twenty-four at fourteen thirty, \
+             one hundred and five kilometres cost two pounds and five pence, five per cent more."
+        );
+        assert_eq!(british.apply("Not 31/04/2024."), "Not 31/04/2024.");
+    }
+
+    #[test]
+    fn test_disabled_kinds() {
+        let normalization = Normalization {
+            numbers: false,
+            dates: false,
+            ..Normalization::default()
+        };
+        assert_eq!(
+            normalization.apply("On 2024-09-19, 3 of 5ms."),
+            "On 2024-09-19, 3 of five milliseconds."
+        );
+    }
+}
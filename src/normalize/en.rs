@@ -0,0 +1,344 @@
+//! English number words, in American and British conventions.
+
+use super::{Currency, Language, Meridiem};
+
+const ONES: [&str; 20] = [
+    "zero",
+    "one",
+    "two",
+    "three",
+    "four",
+    "five",
+    "six",
+    "seven",
+    "eight",
+    "nine",
+    "ten",
+    "eleven",
+    "twelve",
+    "thirteen",
+    "fourteen",
+    "fifteen",
+    "sixteen",
+    "seventeen",
+    "eighteen",
+    "nineteen",
+];
+
+const TENS: [&str; 10] = [
+    "", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+];
+
+const SCALES: [&str; 7] = [
+    "",
+    "thousand",
+    "million",
+    "billion",
+    "trillion",
+    "quadrillion",
+    "quintillion",
+];
+
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+/// Unit symbols with their singular and plural names, spelled the American
+/// way.
+const UNITS: &[(&str, &str, &str)] = &[
+    ("ns", "nanosecond", "nanoseconds"),
+    ("µs", "microsecond", "microseconds"),
+    ("μs", "microsecond", "microseconds"),
+    ("us", "microsecond", "microseconds"),
+    ("ms", "millisecond", "milliseconds"),
+    ("sec", "second", "seconds"),
+    ("min", "minute", "minutes"),
+    ("h", "hour", "hours"),
+    ("hr", "hour", "hours"),
+    ("hrs", "hour", "hours"),
+    ("kB", "kilobyte", "kilobytes"),
+    ("KB", "kilobyte", "kilobytes"),
+    ("KiB", "kibibyte", "kibibytes"),
+    ("MB", "megabyte", "megabytes"),
+    ("MiB", "mebibyte", "mebibytes"),
+    ("GB", "gigabyte", "gigabytes"),
+    ("GiB", "gibibyte", "gibibytes"),
+    ("TB", "terabyte", "terabytes"),
+    ("TiB", "tebibyte", "tebibytes"),
+    ("PB", "petabyte", "petabytes"),
+    ("kbps", "kilobit per second", "kilobits per second"),
+    ("Kbps", "kilobit per second", "kilobits per second"),
+    ("Mbps", "megabit per second", "megabits per second"),
+    ("Gbps", "gigabit per second", "gigabits per second"),
+    ("fps", "frame per second", "frames per second"),
+    ("rpm", "revolution per minute", "revolutions per minute"),
+    ("Hz", "hertz", "hertz"),
+    ("kHz", "kilohertz", "kilohertz"),
+    ("MHz", "megahertz", "megahertz"),
+    ("GHz", "gigahertz", "gigahertz"),
+    ("mm", "millimeter", "millimeters"),
+    ("cm", "centimeter", "centimeters"),
+    ("km", "kilometer", "kilometers"),
+    ("km/h", "kilometer per hour", "kilometers per hour"),
+    ("mph", "mile per hour", "miles per hour"),
+    ("kg", "kilogram", "kilograms"),
+    ("mg", "milligram", "milligrams"),
+    ("lb", "pound", "pounds"),
+    ("lbs", "pound", "pounds"),
+    ("°C", "degree Celsius", "degrees Celsius"),
+    ("°F", "degree Fahrenheit", "degrees Fahrenheit"),
+    ("px", "pixel", "pixels"),
+    ("dpi", "dot per inch", "dots per inch"),
+    ("x", "times", "times"),
+];
+
+/// British spellings of the unit names that differ.
+const BRITISH_UNITS: &[(&str, &str, &str)] = &[
+    ("mm", "millimetre", "millimetres"),
+    ("cm", "centimetre", "centimetres"),
+    ("km", "kilometre", "kilometres"),
+    ("km/h", "kilometre per hour", "kilometres per hour"),
+];
+
+pub(super) struct English {
+    /// Say "one hundred and five", "the nineteenth of September" and
+    /// "fourteen thirty".
+    british: bool,
+}
+
+impl English {
+    pub(super) const US: English = English { british: false };
+    pub(super) const GB: English = English { british: true };
+
+    /// Words for 1 to 999.
+    fn hundreds(&self, number: u64) -> String {
+        let tens = match number % 100 {
+            0 => None,
+            tens @ 1..=19 => Some(ONES[tens as usize].to_string()),
+            tens if tens.is_multiple_of(10) => Some(TENS[tens as usize / 10].to_string()),
+            tens => Some(format!(
+                "{}-{}",
+                TENS[tens as usize / 10],
+                ONES[tens as usize % 10]
+            )),
+        };
+        match (number / 100, tens) {
+            (0, Some(tens)) => tens,
+            (hundreds, None) => format!("{} hundred", ONES[hundreds as usize]),
+            (hundreds, Some(tens)) => {
+                format!("{} hundred{}{tens}", ONES[hundreds as usize], self.joiner())
+            }
+        }
+    }
+
+    /// What joins hundreds to the tens that follow them.
+    fn joiner(&self) -> &'static str {
+        match self.british {
+            true => " and ",
+            false => " ",
+        }
+    }
+}
+
+impl Language for English {
+    fn cardinal(&self, number: u64) -> String {
+        if number == 0 {
+            return ONES[0].to_string();
+        }
+        let groups: Vec<(usize, u64)> = std::iter::successors(Some(number), |rest| {
+            Some(rest / 1000).filter(|rest| *rest > 0)
+        })
+        .map(|rest| rest % 1000)
+        .enumerate()
+        .filter(|(_, group)| *group > 0)
+        .collect();
+        groups
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(position, &(scale, group))| {
+                let words = match scale {
+                    0 => self.hundreds(group),
+                    _ => format!("{} {}", self.hundreds(group), SCALES[scale]),
+                };
+                // "one thousand and five", but "one thousand one hundred".
+                let last = scale == 0 && position > 0;
+                match last && group < 100 {
+                    true => format!("{}{words}", self.joiner().trim_start()),
+                    false => words,
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn ordinal(&self, number: u64) -> String {
+        let cardinal = self.cardinal(number);
+        let (head, last) = cardinal.split_at(cardinal.rfind([' ', '-']).map_or(0, |ii| ii + 1));
+        let last = match last {
+            "one" => "first".to_string(),
+            "two" => "second".to_string(),
+            "three" => "third".to_string(),
+            "five" => "fifth".to_string(),
+            "eight" => "eighth".to_string(),
+            "nine" => "ninth".to_string(),
+            "twelve" => "twelfth".to_string(),
+            word => match word.strip_suffix('y') {
+                Some(stem) => format!("{stem}ieth"),
+                None => format!("{word}th"),
+            },
+        };
+        format!("{head}{last}")
+    }
+
+    fn point(&self) -> &'static str {
+        "point"
+    }
+
+    fn minus(&self) -> &'static str {
+        "minus"
+    }
+
+    fn year(&self, year: u64) -> String {
+        // "twenty twenty-four", "nineteen oh five", "two thousand nine".
+        match (year / 100, year % 100) {
+            (10..=99, 0) if !year.is_multiple_of(1000) => {
+                format!("{} hundred", self.cardinal(year / 100))
+            }
+            (10..=99, low @ 1..=9) if year / 1000 != 2 => {
+                format!("{} oh {}", self.cardinal(year / 100), self.cardinal(low))
+            }
+            (high @ 10..=99, low @ 10..=99) => {
+                format!("{} {}", self.cardinal(high), self.cardinal(low))
+            }
+            _ => self.cardinal(year),
+        }
+    }
+
+    fn date(&self, year: u64, month: u64, day: u64) -> String {
+        let year = self.year(year);
+        let month = MONTHS[month as usize - 1];
+        match self.british {
+            true => format!("the {} of {month} {year}", self.ordinal(day)),
+            false => format!("{month} {}, {year}", self.ordinal(day)),
+        }
+    }
+
+    fn month_day<'a>(&self, first: &'a str, second: &'a str) -> (&'a str, &'a str) {
+        match self.british {
+            true => (second, first),
+            false => (first, second),
+        }
+    }
+
+    fn time(&self, hour: u64, minute: u64, meridiem: Option<Meridiem>) -> String {
+        let minutes = |on_the_hour: &str| match minute {
+            0 => on_the_hour.to_string(),
+            1..=9 => format!(" oh {}", self.cardinal(minute)),
+            _ => format!(" {}", self.cardinal(minute)),
+        };
+        // British English reads a 24-hour clock as written.
+        if self.british && meridiem.is_none() {
+            let on_the_hour = if hour <= 12 { " o'clock" } else { " hundred" };
+            return format!("{}{}", self.cardinal(hour), minutes(on_the_hour));
+        }
+        let meridiem = meridiem.or(match hour {
+            0 => Some(Meridiem::Am),
+            13.. => Some(Meridiem::Pm),
+            _ => None,
+        });
+        let hour = match hour % 12 {
+            0 => 12,
+            hour => hour,
+        };
+        match meridiem {
+            Some(Meridiem::Am) => format!("{}{} AM", self.cardinal(hour), minutes("")),
+            Some(Meridiem::Pm) => format!("{}{} PM", self.cardinal(hour), minutes("")),
+            None => format!("{}{}", self.cardinal(hour), minutes(" o'clock")),
+        }
+    }
+
+    fn unit(&self, symbol: &str) -> Option<(&'static str, &'static str)> {
+        let british = match self.british {
+            true => BRITISH_UNITS,
+            false => &[],
+        };
+        british
+            .iter()
+            .chain(UNITS)
+            .find(|(unit, _, _)| *unit == symbol)
+            .map(|&(_, singular, plural)| (singular, plural))
+    }
+
+    fn currency(&self, symbol: char) -> Option<Currency> {
+        let (major, minor) = match symbol {
+            '$' => (("dollar", "dollars"), Some(("cent", "cents"))),
+            '€' => (("euro", "euros"), Some(("cent", "cents"))),
+            '£' => (("pound", "pounds"), Some(("penny", "pence"))),
+            '¥' => (("yen", "yen"), None),
+            _ => return None,
+        };
+        Some(Currency { major, minor })
+    }
+
+    fn scale(&self, suffix: &str) -> Option<&'static str> {
+        match suffix {
+            "K" | "k" | "thousand" => Some("thousand"),
+            "M" | "million" => Some("million"),
+            "B" | "bn" | "billion" => Some("billion"),
+            "T" | "trillion" => Some("trillion"),
+            _ => None,
+        }
+    }
+
+    fn and(&self) -> &'static str {
+        "and"
+    }
+
+    fn percent(&self) -> &'static str {
+        match self.british {
+            true => "per cent",
+            false => "percent",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cardinal_and_ordinal() {
+        assert_eq!(English::US.cardinal(1_000_005), "one million five");
+        assert_eq!(English::GB.cardinal(1_000_005), "one million and five");
+        assert_eq!(
+            English::GB.cardinal(2_342),
+            "two thousand three hundred and forty-two"
+        );
+        assert_eq!(English::US.ordinal(1_000_000), "one millionth");
+        assert_eq!(English::US.ordinal(40), "fortieth");
+        assert_eq!(
+            English::US.date(2009, 5, 2),
+            "May second, two thousand nine"
+        );
+        assert_eq!(
+            English::US.date(1905, 1, 1),
+            "January first, nineteen oh five"
+        );
+        assert_eq!(
+            English::US.date(1900, 1, 1),
+            "January first, nineteen hundred"
+        );
+    }
+}
@@ -0,0 +1,470 @@
+//! Writing chunks to numbered files with a manifest.
+
+use crate::chunk::{content_hash, Chunk};
+use crate::error::Error;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::ops::{Range, RangeInclusive};
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Name of the manifest written next to the chunk files.
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Name of the combined manifest of a batch, written in the output directory.
+pub const BATCH_MANIFEST_FILE: &str = "batch.json";
+
+/// What to do when the output directory already holds files from a run.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Existing {
+    /// Refuse to write if any chunk file or the manifest already exists.
+    #[default]
+    Error,
+    /// Overwrite files with the same names and leave others alone.
+    Overwrite,
+    /// Delete the files listed in the previous manifest before writing.
+    Clean,
+}
+
+names!(Existing, "existing-files policy", {
+    Error => "error",
+    Overwrite => "overwrite",
+    Clean => "clean",
+});
+
+/// A chunk file name pattern. `{stem}` expands to the input file stem and
+/// `{index}` to the one-based chunk number, optionally zero-padded as in
+/// `{index:04}`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileNameTemplate(String);
+
+impl Default for FileNameTemplate {
+    fn default() -> Self {
+        FileNameTemplate("{index:04}.txt".to_string())
+    }
+}
+
+impl FileNameTemplate {
+    /// Parse a template, rejecting unknown placeholders and templates
+    /// without `{index}`, which would give every chunk the same name.
+    pub fn new(template: impl Into<String>) -> Result<Self, Error> {
+        let template = FileNameTemplate(template.into());
+        if template.render("", 1)? == template.render("", 2)? {
+            return Err(Error::invalid(format!(
+                "file name template {:?} has no {{index}}",
+                template.0
+            )));
+        }
+        Ok(template)
+    }
+
+    /// The file name for chunk `index` of the document named `stem`.
+    pub fn render(&self, stem: &str, index: usize) -> Result<String, Error> {
+        static PLACEHOLDER: OnceLock<Regex> = OnceLock::new();
+        let placeholder = PLACEHOLDER
+            .get_or_init(|| Regex::new(r"\{(\w+)(?::(0?)(\d+))?\}").expect("valid regex"));
+        let mut unknown = None;
+        let name = placeholder.replace_all(&self.0, |caps: &regex::Captures| {
+            let width = caps
+                .get(3)
+                .map_or(0, |width| width.as_str().parse().unwrap_or(0));
+            let zero = caps.get(2).is_some_and(|zero| !zero.as_str().is_empty());
+            match (&caps[1], zero) {
+                ("stem", _) => format!("{stem:width$}"),
+                ("index", true) => format!("{index:0width$}"),
+                ("index", false) => format!("{index:width$}"),
+                (name, _) => {
+                    unknown.get_or_insert_with(|| name.to_string());
+                    String::new()
+                }
+            }
+        });
+        match unknown {
+            Some(name) => Err(Error::invalid(format!(
+                "unknown placeholder {{{name}}} in {:?}",
+                self.0
+            ))),
+            None if name.contains(['/', '\\']) => Err(Error::invalid(format!(
+                "file name template {:?} must not contain a path",
+                self.0
+            ))),
+            None => Ok(name.into_owned()),
+        }
+    }
+}
+
+/// One written chunk file, as listed in the manifest.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub file: String,
+    pub index: usize,
+    pub length: usize,
+    pub byte_range: Range<usize>,
+    pub line_range: RangeInclusive<usize>,
+    pub heading_path: Vec<String>,
+    #[serde(default)]
+    pub title: Option<String>,
+    /// SHA-256 of the chunk's text; missing from manifests written before
+    /// chunks were hashed.
+    #[serde(default)]
+    pub hash: Option<String>,
+    /// The chunk's synthesized audio file, once there is one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio: Option<String>,
+}
+
+impl ManifestEntry {
+    /// The chunk this entry lists, with `text` read from its file.
+    pub fn chunk(&self, text: String) -> Chunk {
+        Chunk {
+            index: self.index,
+            length: self.length,
+            hash: content_hash(&text),
+            text,
+            byte_range: self.byte_range.clone(),
+            line_range: self.line_range.clone(),
+            heading_path: self.heading_path.clone(),
+            title: self.title.clone(),
+        }
+    }
+}
+
+/// The chunk files of one document, in reading order.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub chunks: Vec<ManifestEntry>,
+    /// Whether the chunks were packed with stable boundaries, so that an
+    /// edit leaves the chunks away from it, and their cached audio, alone.
+    #[serde(default)]
+    pub stable_boundaries: bool,
+}
+
+impl Manifest {
+    /// Read a manifest written by [`write`](Manifest::write).
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, Error> {
+        read_json(path.as_ref())
+    }
+
+    /// Write the manifest as pretty-printed JSON.
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        write_json(path.as_ref(), self)
+    }
+}
+
+/// One document of a batch, as listed in the combined manifest.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchEntry {
+    /// The input path, or `-` for standard input.
+    pub input: String,
+    /// Directory of the document's chunk files, relative to the output
+    /// directory.
+    pub directory: String,
+    /// The document's chunk files, or none if it failed.
+    pub chunks: Vec<ManifestEntry>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The documents of a batch, in input order.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchManifest {
+    pub documents: Vec<BatchEntry>,
+}
+
+impl BatchManifest {
+    /// Read a batch manifest, such as [`BATCH_MANIFEST_FILE`].
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, Error> {
+        read_json(path.as_ref())
+    }
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T, Error> {
+    let json = fs::read_to_string(path).map_err(|error| Error::io(path, error))?;
+    serde_json::from_str(&json).map_err(|error| Error::parse(path.display(), error))
+}
+
+fn write_json(path: &Path, value: &impl Serialize) -> Result<(), Error> {
+    let json = serde_json::to_string_pretty(value).expect("manifests serialize");
+    fs::write(path, json).map_err(|error| Error::io(path, error))
+}
+
+/// `path`, a relative path listed in the manifest at `manifest`, below
+/// `dir`. Anything that could reach outside `dir`, such as `..` or an
+/// absolute path, is an error, so that cleaning never deletes other files.
+fn listed(dir: &Path, path: &str, manifest: &Path) -> Result<PathBuf, Error> {
+    let mut components = Path::new(path).components().peekable();
+    let plain = components.peek().is_some()
+        && components.all(|component| matches!(component, Component::Normal(_)));
+    match plain {
+        true => Ok(dir.join(path)),
+        false => Err(Error::parse(
+            manifest.display(),
+            format!("{path:?} is not a path inside the output directory"),
+        )),
+    }
+}
+
+/// A file name listed in the manifest at `manifest`, in `dir`. Only a
+/// single plain file name is accepted.
+fn listed_file(dir: &Path, file: &str, manifest: &Path) -> Result<PathBuf, Error> {
+    match Path::new(file).components().count() {
+        1 => listed(dir, file, manifest),
+        _ => Err(Error::parse(
+            manifest.display(),
+            format!("{file:?} is not a file name"),
+        )),
+    }
+}
+
+/// Delete the files at `paths` that exist.
+fn remove<'a>(paths: impl IntoIterator<Item = &'a PathBuf>) -> Result<(), Error> {
+    paths
+        .into_iter()
+        .filter(|path| path.exists())
+        .try_for_each(|path| fs::remove_file(path).map_err(|error| Error::io(path, error)))
+}
+
+/// Writes each chunk to its own file in a directory.
+#[derive(Clone, Debug)]
+pub struct OutputDir {
+    path: PathBuf,
+    template: FileNameTemplate,
+    existing: Existing,
+    stable_boundaries: bool,
+}
+
+impl OutputDir {
+    /// Write to the directory at `path`, creating it if needed, with the
+    /// default file names and refusing to overwrite an earlier run.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        OutputDir {
+            path: path.into(),
+            template: FileNameTemplate::default(),
+            existing: Existing::default(),
+            stable_boundaries: false,
+        }
+    }
+
+    /// How chunk files are named.
+    pub fn template(mut self, template: FileNameTemplate) -> Self {
+        self.template = template;
+        self
+    }
+
+    /// What to do when the directory holds an earlier run.
+    pub fn existing(mut self, existing: Existing) -> Self {
+        self.existing = existing;
+        self
+    }
+
+    /// Record in manifests that the chunks were packed with
+    /// [stable boundaries](crate::Chunker::stable_boundaries).
+    pub fn stable_boundaries(mut self, stable_boundaries: bool) -> Self {
+        self.stable_boundaries = stable_boundaries;
+        self
+    }
+
+    /// Where the document named `name` of a batch is written, as a
+    /// subdirectory with the same settings.
+    pub fn document(&self, name: &str) -> OutputDir {
+        OutputDir {
+            path: self.path.join(name),
+            ..self.clone()
+        }
+    }
+
+    /// Get ready to write a batch: refuse if an earlier batch manifest exists
+    /// and existing files are an error, or delete the files it lists when
+    /// cleaning.
+    pub fn prepare_batch(&self) -> Result<(), Error> {
+        let batch_path = self.path.join(BATCH_MANIFEST_FILE);
+        match self.existing {
+            Existing::Error if batch_path.exists() => Err(Error::Exists(batch_path)),
+            Existing::Clean if batch_path.exists() => {
+                let paths = BatchManifest::read(&batch_path)?
+                    .documents
+                    .iter()
+                    .map(|document| {
+                        let directory = listed(&self.path, &document.directory, &batch_path)?;
+                        let files = document
+                            .chunks
+                            .iter()
+                            .map(|entry| listed_file(&directory, &entry.file, &batch_path))
+                            .collect::<Result<Vec<_>, Error>>()?;
+                        Ok([files, vec![directory.join(MANIFEST_FILE)]].concat())
+                    })
+                    .collect::<Result<Vec<_>, Error>>()?;
+                remove(paths.iter().flatten())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Write the combined manifest of a batch, after its documents.
+    pub fn write_batch(&self, manifest: &BatchManifest) -> Result<(), Error> {
+        fs::create_dir_all(&self.path).map_err(|error| Error::io(&self.path, error))?;
+        write_json(&self.path.join(BATCH_MANIFEST_FILE), manifest)
+    }
+
+    /// Write `chunks` and the manifest, naming files after `stem`.
+    pub fn write(&self, stem: &str, chunks: &[Chunk]) -> Result<Manifest, Error> {
+        fs::create_dir_all(&self.path).map_err(|error| Error::io(&self.path, error))?;
+        let manifest_path = self.path.join(MANIFEST_FILE);
+
+        let manifest = Manifest {
+            chunks: chunks
+                .iter()
+                .map(|chunk| {
+                    Ok(ManifestEntry {
+                        file: self.template.render(stem, chunk.index + 1)?,
+                        index: chunk.index,
+                        length: chunk.length,
+                        byte_range: chunk.byte_range.clone(),
+                        line_range: chunk.line_range.clone(),
+                        heading_path: chunk.heading_path.clone(),
+                        title: chunk.title.clone(),
+                        hash: Some(chunk.hash.clone()),
+                        audio: None,
+                    })
+                })
+                .collect::<Result<_, Error>>()?,
+            stable_boundaries: self.stable_boundaries,
+        };
+
+        match self.existing {
+            Existing::Error => {
+                let files = manifest
+                    .chunks
+                    .iter()
+                    .map(|entry| self.path.join(&entry.file));
+                if let Some(path) = std::iter::once(manifest_path.clone())
+                    .chain(files)
+                    .find(|path| path.exists())
+                {
+                    return Err(Error::Exists(path));
+                }
+            }
+            Existing::Overwrite => {}
+            Existing::Clean if manifest_path.exists() => {
+                let paths = Manifest::read(&manifest_path)?
+                    .chunks
+                    .iter()
+                    .map(|entry| listed_file(&self.path, &entry.file, &manifest_path))
+                    .collect::<Result<Vec<_>, Error>>()?;
+                remove(&paths)?;
+            }
+            Existing::Clean => {}
+        }
+
+        chunks
+            .iter()
+            .zip(&manifest.chunks)
+            .try_for_each(|(chunk, entry)| {
+                let path = self.path.join(&entry.file);
+                fs::write(&path, &chunk.text).map_err(|error| Error::io(&path, error))
+            })?;
+        manifest.write(&manifest_path)?;
+
+        Ok(manifest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Chunker;
+
+    #[test]
+    fn test_template_render() {
+        let template = FileNameTemplate::new("{stem}-{index:04}.txt").unwrap();
+        assert_eq!(template.render("guide", 7).unwrap(), "guide-0007.txt");
+        assert!(FileNameTemplate::new("{chapter}.txt").is_err());
+        assert!(FileNameTemplate::new("../{index}.txt").is_err());
+        assert!(FileNameTemplate::new("{stem}.txt").is_err());
+    }
+
+    #[test]
+    fn test_write_existing() {
+        let dir = std::env::temp_dir().join(format!("listnr-output-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let chunks = Chunker::new().limit(12).chunk("One two. Three four. Five.");
+
+        let manifest = OutputDir::new(&dir).write("doc", &chunks).unwrap();
+        assert_eq!(manifest.chunks.len(), 3);
+        assert_eq!(
+            fs::read_to_string(dir.join("0001.txt")).unwrap(),
+            "One two."
+        );
+        assert!(OutputDir::new(&dir).write("doc", &chunks).is_err());
+
+        let fewer = Chunker::new().chunk("One two.");
+        let manifest = OutputDir::new(&dir)
+            .existing(Existing::Clean)
+            .stable_boundaries(true)
+            .write("doc", &fewer)
+            .unwrap();
+        assert!(
+            Manifest::read(dir.join(MANIFEST_FILE))
+                .unwrap()
+                .stable_boundaries
+        );
+        assert_eq!(manifest.chunks.len(), 1);
+        assert!(dir.join("0001.txt").exists());
+        assert!(!dir.join("0003.txt").exists());
+
+        let victim = dir.with_extension("victim");
+        fs::write(&victim, "Keep me.").unwrap();
+        let mut manifest = Manifest::read(dir.join(MANIFEST_FILE)).unwrap();
+        manifest.chunks[0].file = format!("../{}", victim.file_name().unwrap().to_string_lossy());
+        manifest.write(dir.join(MANIFEST_FILE)).unwrap();
+        let clean = OutputDir::new(&dir).existing(Existing::Clean);
+        assert!(clean.write("doc", &fewer).is_err());
+        assert!(victim.exists());
+        fs::remove_file(&victim).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_batch() {
+        let dir = std::env::temp_dir().join(format!("listnr-batch-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let output = OutputDir::new(&dir);
+        let chunks = Chunker::new().chunk("One two.");
+        output.prepare_batch().unwrap();
+        let manifest = BatchManifest {
+            documents: vec![BatchEntry {
+                input: "guide/intro.md".to_string(),
+                directory: "guide/intro".to_string(),
+                chunks: output
+                    .document("guide/intro")
+                    .write("intro", &chunks)
+                    .unwrap()
+                    .chunks,
+                error: None,
+            }],
+        };
+        output.write_batch(&manifest).unwrap();
+        assert!(dir.join("guide/intro/0001.txt").exists());
+        assert_eq!(
+            BatchManifest::read(dir.join(BATCH_MANIFEST_FILE)).unwrap(),
+            manifest
+        );
+        assert!(output.prepare_batch().is_err());
+
+        output
+            .clone()
+            .existing(Existing::Clean)
+            .prepare_batch()
+            .unwrap();
+        assert!(!dir.join("guide/intro/0001.txt").exists());
+
+        let mut escaping = manifest;
+        escaping.documents[0].directory = "guide/../..".to_string();
+        output.write_batch(&escaping).unwrap();
+        let clean = output.clone().existing(Existing::Clean);
+        assert!(clean.prepare_batch().is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,281 @@
+//! Spoken rendering of markdown nodes.
+//!
+//! Each node type that carries meaning beyond its text, such as list
+//! position, link targets, image alt text and table headers, is read out
+//! according to a configurable style.
+
+use crate::ssml::{self, Markup};
+use comrak::nodes::{AstNode, ListType, NodeValue};
+use regex::Regex;
+use std::sync::OnceLock;
+
+/// How list items are introduced.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ListStyle {
+    /// Introduce ordered list items as "Item 1 of 3", numbered from the
+    /// list's start.
+    #[default]
+    Count,
+    /// Read list items without an introduction.
+    Plain,
+}
+
+names!(ListStyle, "list style", {
+    Count => "count",
+    Plain => "plain",
+});
+
+/// How links are read.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum LinkStyle {
+    /// Read the link text only.
+    #[default]
+    Text,
+    /// Follow the link text with the domain it points to.
+    Domain,
+    /// Follow the link text with the full URL.
+    Url,
+}
+
+names!(LinkStyle, "link style", {
+    Text => "text",
+    Domain => "domain",
+    Url => "url",
+});
+
+/// How images are read.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ImageStyle {
+    /// Read the alt text as "Image: ...".
+    #[default]
+    Alt,
+    /// Skip images.
+    Omit,
+}
+
+names!(ImageStyle, "image style", {
+    Alt => "alt",
+    Omit => "omit",
+});
+
+/// How tables are read.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum TableStyle {
+    /// Read each row as a sentence, labelling cells with their column header.
+    #[default]
+    Rows,
+    /// Skip tables.
+    Omit,
+}
+
+names!(TableStyle, "table style", {
+    Rows => "rows",
+    Omit => "omit",
+});
+
+/// How footnote references and definitions are read.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum FootnoteStyle {
+    /// Announce references and read definitions where they appear.
+    #[default]
+    Read,
+    /// Skip footnote references and definitions.
+    Omit,
+}
+
+names!(FootnoteStyle, "footnote style", {
+    Read => "read",
+    Omit => "omit",
+});
+
+/// How raw HTML blocks are read.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum HtmlStyle {
+    /// Read the text between tags.
+    #[default]
+    Text,
+    /// Skip raw HTML blocks.
+    Omit,
+}
+
+names!(HtmlStyle, "HTML style", {
+    Text => "text",
+    Omit => "omit",
+});
+
+/// Per-node-type rendering styles.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RenderOptions {
+    pub lists: ListStyle,
+    pub links: LinkStyle,
+    pub images: ImageStyle,
+    pub tables: TableStyle,
+    pub footnotes: FootnoteStyle,
+    pub html: HtmlStyle,
+}
+
+impl RenderOptions {
+    /// Concatenate the spoken inline text below a block. Soft breaks read as
+    /// spaces, hard breaks are kept, and emphasis is marked when rendering
+    /// SSML.
+    pub fn inline_text<'a>(&self, node: &'a AstNode<'a>, markup: Markup) -> String {
+        node.children()
+            .map(|node| match node.data.borrow().value {
+                NodeValue::Text(ref text) => text.clone(),
+                NodeValue::Code(ref node_code) => node_code.literal.clone(),
+                NodeValue::SoftBreak => " ".to_string(),
+                NodeValue::LineBreak => "\n".to_string(),
+                NodeValue::HtmlInline(_) => String::new(),
+                NodeValue::Emph if markup == Markup::Ssml => {
+                    ssml::emphasis(&self.inline_text(node, markup), false)
+                }
+                NodeValue::Strong if markup == Markup::Ssml => {
+                    ssml::emphasis(&self.inline_text(node, markup), true)
+                }
+                NodeValue::Link(ref link) => {
+                    let text = self.inline_text(node, markup);
+                    match self.links {
+                        LinkStyle::Text => text,
+                        LinkStyle::Domain => format!("{text} (link to {})", domain(&link.url)),
+                        LinkStyle::Url => format!("{text} ({})", link.url),
+                    }
+                }
+                NodeValue::Image(_) => match self.images {
+                    ImageStyle::Alt => {
+                        let alt = self.inline_text(node, markup);
+                        match alt.trim() {
+                            "" => "Image.".to_string(),
+                            alt => format!("Image: {}.", alt.trim_end_matches('.')),
+                        }
+                    }
+                    ImageStyle::Omit => String::new(),
+                },
+                NodeValue::FootnoteReference(ref reference) => match self.footnotes {
+                    FootnoteStyle::Read => format!(" (footnote {})", reference.name),
+                    FootnoteStyle::Omit => String::new(),
+                },
+                _ => self.inline_text(node, markup),
+            })
+            .collect()
+    }
+
+    /// Text introducing a paragraph: its list position, task state or
+    /// footnote name.
+    /// `None` if the paragraph should not be read at all.
+    pub fn paragraph_prefix<'a>(&self, paragraph: &'a AstNode<'a>) -> Option<String> {
+        let parent = paragraph.parent()?;
+        let is_first = paragraph.previous_sibling().is_none();
+        let prefix = match parent.data.borrow().value {
+            NodeValue::Item(_) if is_first && self.lists == ListStyle::Count => {
+                let list = parent.parent()?;
+                match list.data.borrow().value {
+                    NodeValue::List(ref list_info) if list_info.list_type == ListType::Ordered => {
+                        // Lists may start at any number, as in "3." after a
+                        // break in the numbering.
+                        let start = list_info.start.saturating_sub(1);
+                        let position = start + parent.preceding_siblings().count();
+                        let total = start + list.children().count();
+                        format!("Item {position} of {total}: ")
+                    }
+                    _ => String::new(),
+                }
+            }
+            NodeValue::TaskItem(checked) if is_first => match checked {
+                Some(_) => "Done: ".to_string(),
+                None => "To do: ".to_string(),
+            },
+            NodeValue::FootnoteDefinition(ref definition) => match self.footnotes {
+                FootnoteStyle::Omit => return None,
+                FootnoteStyle::Read if is_first => format!("Footnote {}: ", definition.name),
+                FootnoteStyle::Read => String::new(),
+            },
+            _ => String::new(),
+        };
+        Some(prefix)
+    }
+
+    /// A table row read as a sentence, or `None` if tables are omitted.
+    pub fn table_row<'a>(&self, row: &'a AstNode<'a>, markup: Markup) -> Option<String> {
+        if self.tables == TableStyle::Omit {
+            return None;
+        }
+        let cells = |row: &'a AstNode<'a>| -> Vec<String> {
+            row.children()
+                .map(|cell| self.inline_text(cell, markup).trim().to_string())
+                .collect()
+        };
+        let is_header = matches!(row.data.borrow().value, NodeValue::TableRow(true));
+        if is_header {
+            return Some(format!("Table with columns {}.", cells(row).join(", ")));
+        }
+        let headers = row
+            .parent()
+            .and_then(|table| table.first_child())
+            .filter(|first| matches!(first.data.borrow().value, NodeValue::TableRow(true)))
+            .map(cells)
+            .unwrap_or_default();
+        let sentence = cells(row)
+            .into_iter()
+            .enumerate()
+            .filter(|(_, cell)| !cell.is_empty())
+            .map(
+                |(ii, cell)| match headers.get(ii).filter(|header| !header.is_empty()) {
+                    Some(header) => format!("{}: {cell}", ssml::strip(header)),
+                    None => cell,
+                },
+            )
+            .collect::<Vec<_>>()
+            .join(", ");
+        Some(format!("{sentence}."))
+    }
+
+    /// The readable text of a raw HTML block, or `None` if HTML is omitted.
+    pub fn html_block(&self, literal: &str) -> Option<String> {
+        match self.html {
+            HtmlStyle::Text => {
+                static MARKUP: OnceLock<Regex> = OnceLock::new();
+                let markup = MARKUP.get_or_init(|| {
+                    Regex::new(r"(?s)<!--.*?-->|<[^>]*>").expect("HTML pattern is valid")
+                });
+                let text = markup.replace_all(literal, " ");
+                Some(text.split_whitespace().collect::<Vec<_>>().join(" "))
+            }
+            HtmlStyle::Omit => None,
+        }
+    }
+}
+
+/// The host of a URL without its scheme or a leading "www.".
+pub(crate) fn domain(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let rest = rest.strip_prefix("mailto:").unwrap_or(rest);
+    let host = rest.split(['/', '?', '#']).next().unwrap_or(rest);
+    host.strip_prefix("www.").unwrap_or(host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_domain() {
+        assert_eq!(domain("https://www.example.com/a?b"), "example.com");
+        assert_eq!(domain("docs.rs"), "docs.rs");
+    }
+
+    #[test]
+    fn test_html_block() {
+        let options = RenderOptions::default();
+        assert_eq!(
+            options.html_block("<div><!-- note --><p>Hello\n <b>there</b></p></div>"),
+            Some("Hello there".to_string())
+        );
+    }
+}
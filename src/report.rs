@@ -0,0 +1,182 @@
+//! Audits of the substitutions made in a document.
+
+use crate::substitution::{Hit, SubstitutionTable};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Where a substitution was made, as one-based line and character column.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// How often one rule fired, and where.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[non_exhaustive]
+pub struct RuleReport {
+    pub pattern: String,
+    pub replacement: String,
+    pub layer: Option<String>,
+    pub count: usize,
+    pub hits: Vec<Position>,
+}
+
+/// Every rule of a substitution table with the hits it had in a document,
+/// in the table's order.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[non_exhaustive]
+pub struct Report {
+    pub rules: Vec<RuleReport>,
+    /// Patterns of the rules that never fired.
+    pub unused: Vec<String>,
+}
+
+impl Report {
+    /// Count `hits` against the rules of `table`.
+    pub fn new(table: &SubstitutionTable, hits: &[Hit]) -> Self {
+        let rules: Vec<RuleReport> = table
+            .iter()
+            .map(|rule| {
+                let hits: Vec<Position> = hits
+                    .iter()
+                    .filter(|hit| hit.pattern == rule.pattern())
+                    .map(|hit| Position {
+                        line: hit.line,
+                        column: hit.column,
+                    })
+                    .collect();
+                RuleReport {
+                    pattern: rule.pattern().to_string(),
+                    replacement: rule.replacement().to_string(),
+                    layer: rule.layer().map(str::to_string),
+                    count: hits.len(),
+                    hits,
+                }
+            })
+            .collect();
+        let unused = rules
+            .iter()
+            .filter(|rule| rule.count == 0)
+            .map(|rule| rule.pattern.clone())
+            .collect();
+        Report { rules, unused }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fired = self.rules.iter().filter(|rule| rule.count > 0);
+        let total: usize = self.rules.iter().map(|rule| rule.count).sum();
+        writeln!(
+            f,
+            "{total} substitutions by {} of {} rules",
+            fired.clone().count(),
+            self.rules.len()
+        )?;
+        let describe = |rule: &RuleReport| match rule.layer {
+            Some(ref layer) => format!("{:?} -> {:?} ({layer})", rule.pattern, rule.replacement),
+            None => format!("{:?} -> {:?}", rule.pattern, rule.replacement),
+        };
+        fired.clone().try_for_each(|rule| {
+            let positions: Vec<String> = rule
+                .hits
+                .iter()
+                .map(|hit| format!("{}:{}", hit.line, hit.column))
+                .collect();
+            writeln!(
+                f,
+                "{:>5}  {}  at {}",
+                rule.count,
+                describe(rule),
+                positions.join(", ")
+            )
+        })?;
+        if !self.unused.is_empty() {
+            writeln!(f, "Never fired:")?;
+            self.rules
+                .iter()
+                .filter(|rule| rule.count == 0)
+                .try_for_each(|rule| writeln!(f, "       {}", describe(rule)))?;
+        }
+        Ok(())
+    }
+}
+
+/// A unified diff, with no context lines, between `content` and `content`
+/// with the replacements of `hits` made. Hits whose matched text is not at
+/// their position, as where the source escapes it, are left out.
+pub fn diff(name: &str, content: &str, hits: &[Hit]) -> String {
+    let lines: Vec<&str> = content.lines().collect();
+    let mut by_line: BTreeMap<usize, Vec<&Hit>> = BTreeMap::new();
+    hits.iter()
+        .filter(|hit| !hit.matched.contains('\n'))
+        .for_each(|hit| by_line.entry(hit.line).or_default().push(hit));
+
+    let mut diff = format!("--- {name}\n+++ {name} (substituted)\n");
+    by_line.into_iter().for_each(|(line, mut hits)| {
+        let Some(original) = lines.get(line - 1) else {
+            return;
+        };
+        hits.sort_by_key(|hit| hit.column);
+        let mut changed = String::new();
+        let mut rest = 0;
+        hits.iter().for_each(|hit| {
+            let Some(start) = original
+                .char_indices()
+                .map(|(ii, _)| ii)
+                .nth(hit.column - 1)
+                .filter(|&start| start >= rest && original[start..].starts_with(&hit.matched))
+            else {
+                return;
+            };
+            changed.push_str(&original[rest..start]);
+            changed.push_str(&hit.replacement);
+            rest = start + hit.matched.len();
+        });
+        changed.push_str(&original[rest..]);
+        if changed != *original {
+            diff.push_str(&format!("@@ -{line} +{line} @@\n-{original}\n+{changed}\n"));
+        }
+    });
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Markup;
+
+    #[test]
+    fn test_report_and_diff() {
+        let table: SubstitutionTable = [("API", "A P I"), ("SQL", "sequel")].into_iter().collect();
+        let content = "# API\n\nCall the API twice.\n";
+        let (_, hits) = table.substitute(content, None, Markup::Text);
+
+        let report = Report::new(&table, &hits);
+        assert_eq!(report.rules[0].count, 2);
+        assert_eq!(
+            report.rules[0].hits,
+            [
+                Position { line: 1, column: 3 },
+                Position {
+                    line: 3,
+                    column: 10
+                }
+            ]
+        );
+        assert_eq!(report.unused, ["SQL"]);
+        assert_eq!(
+            report.to_string(),
+            "2 substitutions by 1 of 2 rules\n    2  \"API\" -> \"A P I\"  at 1:3, 3:10\n\
+             Never fired:\n       \"SQL\" -> \"sequel\"\n"
+        );
+
+        assert_eq!(
+            diff("doc.md", content, &hits),
+            "--- doc.md\n+++ doc.md (substituted)\n@@ -1 +1 @@\n-# API\n+# A P I\n\
+             @@ -3 +3 @@\n-Call the API twice.\n+Call the A P I twice.\n"
+        );
+    }
+}
@@ -0,0 +1,488 @@
+//! Boundary-aware packing of text into chunks.
+//!
+//! Text is split recursively: a run of blocks that fits under the limit is
+//! kept together, otherwise it is split at paragraphs, then sentences, then
+//! clauses and finally words. Pieces are merged greedily at each level, so a
+//! chunk only ends inside a sentence when that sentence alone does not fit.
+//! Words longer than the limit, such as URLs, are split between characters.
+//!
+//! Forced boundaries, used to start a chunk at each heading section, are
+//! never merged across.
+//!
+//! Greedy merging lets a change in one piece's length move every boundary
+//! after it. For stable boundaries, pieces chosen by a hash of their text are
+//! anchors that start a new chunk once the one before is half full, so an
+//! edit usually only moves the boundaries up to the next anchor.
+
+use crate::ssml;
+use crate::unit::Unit;
+use sha2::{Digest, Sha256};
+use std::ops::RangeInclusive;
+
+/// Strength of a boundary between two pieces of text, weakest first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Break {
+    Word,
+    Clause,
+    Sentence,
+    Paragraph,
+    Block,
+    /// The start of a heading section that may share a chunk with the
+    /// preceding sibling section.
+    Section,
+    /// The start of a heading section that always begins a new chunk.
+    Forced,
+}
+
+impl Break {
+    /// Text inserted between two pieces separated by this boundary.
+    fn separator(self) -> &'static str {
+        match self {
+            Break::Paragraph | Break::Block | Break::Section | Break::Forced => "\n\n",
+            Break::Word | Break::Clause | Break::Sentence => " ",
+        }
+    }
+
+    fn finer(self) -> Option<Break> {
+        match self {
+            Break::Forced => Some(Break::Section),
+            Break::Section => Some(Break::Block),
+            Break::Block => Some(Break::Paragraph),
+            Break::Paragraph => Some(Break::Sentence),
+            Break::Sentence => Some(Break::Clause),
+            Break::Clause => Some(Break::Word),
+            Break::Word => None,
+        }
+    }
+}
+
+/// A piece of text, the boundary that precedes it and the index of the source
+/// block it was taken from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Segment {
+    pub text: String,
+    pub break_before: Break,
+    pub block: usize,
+}
+
+impl Segment {
+    /// A segment of `text` taken from the first block.
+    pub fn new(text: impl Into<String>, break_before: Break) -> Self {
+        Segment {
+            text: text.into(),
+            break_before,
+            block: 0,
+        }
+    }
+}
+
+/// The text of a packed chunk and the range of source blocks it spans.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Packed {
+    pub text: String,
+    pub blocks: RangeInclusive<usize>,
+}
+
+impl Packed {
+    fn extend(&mut self, separator: &str, other: Packed) {
+        self.text.push_str(separator);
+        self.text.push_str(&other.text);
+        self.blocks = *self.blocks.start()..=*other.blocks.end();
+    }
+}
+
+/// Words that are commonly followed by a period without ending a sentence.
+const ABBREVIATIONS: &[&str] = &[
+    "al", "approx", "cf", "co", "corp", "dept", "dr", "e.g", "eg", "est", "fig", "i.e", "ie",
+    "inc", "jr", "ltd", "mr", "mrs", "ms", "mt", "no", "nos", "p", "pp", "prof", "sr", "st", "vol",
+    "vs",
+];
+
+const CLOSERS: &[char] = &['"', '\'', ')', ']', '}', '\u{201d}', '\u{2019}', '*', '_'];
+
+/// Characters that may follow the punctuation ending a sentence or clause.
+fn is_closer(ch: char) -> bool {
+    CLOSERS.contains(&ch) || ssml::is_marker(ch)
+}
+
+/// Maximum size of a chunk and the unit it is measured in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ChunkConfig {
+    pub limit: usize,
+    pub unit: Unit,
+    /// Whether SSML tags count against the limit.
+    pub count_markup: bool,
+    /// Whether to start chunks at content-defined anchors, so that editing
+    /// the document only moves the boundaries near the edit.
+    pub stable: bool,
+}
+
+impl Default for ChunkConfig {
+    fn default() -> Self {
+        ChunkConfig::new(1500, Unit::Chars)
+    }
+}
+
+impl ChunkConfig {
+    /// Pack chunks of up to `limit` in `unit`, counting markup and without
+    /// stable boundaries.
+    pub const fn new(limit: usize, unit: Unit) -> Self {
+        ChunkConfig {
+            limit,
+            unit,
+            count_markup: true,
+            stable: false,
+        }
+    }
+
+    /// Whether `text` fits within the limit.
+    pub fn fits(&self, text: &str) -> bool {
+        self.unit.measure(text) <= self.limit
+    }
+}
+
+/// Whether packing for stable boundaries starts a new chunk at `text`, `length`
+/// long, rather than adding it to a chunk `current` long that it would fit
+/// in. Anchors are chosen by hashing the text, for about one piece in each
+/// stretch of text as long as the limit, and only break chunks at least half
+/// full so that chunks stay long.
+pub fn breaks_at_anchor(current: usize, text: &str, length: usize, limit: usize) -> bool {
+    let digest = Sha256::digest(text.as_bytes());
+    let hash = u64::from_be_bytes(digest[..8].try_into().expect("digest is 32 bytes"));
+    2 * current >= limit && hash % (limit.max(1) as u64) < length as u64
+}
+
+/// Pack `segments` into chunks accepted by `fits`, preferring the strongest
+/// available boundary. A piece that fits is still put in a new chunk when
+/// `anchor` accepts the text of the current chunk and the piece. No chunk is
+/// rejected by `fits` unless it holds a single character.
+pub fn pack(
+    segments: Vec<Segment>,
+    fits: &dyn Fn(&str) -> bool,
+    anchor: &dyn Fn(&str, &str) -> bool,
+) -> Vec<Packed> {
+    split(segments, Break::Forced, fits, anchor)
+}
+
+fn split(
+    segments: Vec<Segment>,
+    level: Break,
+    fits: &dyn Fn(&str) -> bool,
+    anchor: &dyn Fn(&str, &str) -> bool,
+) -> Vec<Packed> {
+    let segments: Vec<Segment> = segments
+        .into_iter()
+        .flat_map(|segment| refine(segment, level))
+        .collect();
+
+    let mut chunks = Vec::new();
+    let mut current: Option<Packed> = None;
+    group(segments, level).into_iter().for_each(|part| {
+        let packed = Packed {
+            text: join(&part),
+            blocks: part[0].block..=part[part.len() - 1].block,
+        };
+        let separator = part[0].break_before.separator();
+        if level == Break::Forced {
+            chunks.extend(split(part, Break::Section, fits, anchor));
+        } else if !fits(&packed.text) {
+            chunks.extend(current.take());
+            match level.finer() {
+                Some(finer) => chunks.extend(split(part, finer, fits, anchor)),
+                None => chunks.extend(split_chars(packed, fits)),
+            }
+        } else {
+            match current.as_mut() {
+                Some(chunk)
+                    if !anchor(&chunk.text, &packed.text)
+                        && fits(&[chunk.text.as_str(), separator, &packed.text].concat()) =>
+                {
+                    chunk.extend(separator, packed)
+                }
+                _ => {
+                    chunks.extend(current.take());
+                    current = Some(packed);
+                }
+            }
+        }
+    });
+    chunks.extend(current);
+    chunks
+}
+
+/// Break a segment into finer pieces when `level` is below paragraph level.
+fn refine(segment: Segment, level: Break) -> Vec<Segment> {
+    let pieces = match level {
+        Break::Sentence => split_sentences(&segment.text),
+        Break::Clause => split_clauses(&segment.text),
+        Break::Word => segment.text.split_whitespace().collect(),
+        Break::Paragraph | Break::Block | Break::Section | Break::Forced => return vec![segment],
+    };
+    pieces
+        .into_iter()
+        .enumerate()
+        .map(|(ii, text)| {
+            let break_before = if ii == 0 { segment.break_before } else { level };
+            Segment {
+                block: segment.block,
+                ..Segment::new(text, break_before)
+            }
+        })
+        .collect()
+}
+
+/// Group consecutive segments, starting a new group at each boundary at least
+/// as strong as `level`.
+fn group(segments: Vec<Segment>, level: Break) -> Vec<Vec<Segment>> {
+    segments
+        .into_iter()
+        .fold(Vec::new(), |mut groups: Vec<Vec<Segment>>, segment| {
+            match groups.last_mut() {
+                Some(last) if segment.break_before < level => last.push(segment),
+                _ => groups.push(vec![segment]),
+            }
+            groups
+        })
+}
+
+fn join(segments: &[Segment]) -> String {
+    segments
+        .iter()
+        .enumerate()
+        .fold(String::new(), |mut text, (ii, segment)| {
+            if ii > 0 {
+                text.push_str(segment.break_before.separator());
+            }
+            text.push_str(&segment.text);
+            text
+        })
+}
+
+/// Split an unbroken token into pieces accepted by `fits`, never cutting
+/// through a character.
+fn split_chars(packed: Packed, fits: &dyn Fn(&str) -> bool) -> Vec<Packed> {
+    packed
+        .text
+        .chars()
+        .fold(Vec::new(), |mut pieces: Vec<Packed>, ch| {
+            match pieces.last_mut() {
+                Some(piece) if fits(&format!("{}{ch}", piece.text)) => piece.text.push(ch),
+                _ => pieces.push(Packed {
+                    text: ch.to_string(),
+                    blocks: packed.blocks.clone(),
+                }),
+            }
+            pieces
+        })
+}
+
+/// Split text into sentences, keeping abbreviations, initials and decimal
+/// numbers intact.
+pub fn split_sentences(text: &str) -> Vec<&str> {
+    split_after(text, is_sentence_end)
+}
+
+/// Split a sentence after commas, semicolons, colons and dashes.
+pub fn split_clauses(text: &str) -> Vec<&str> {
+    split_after(text, |text, end, _| {
+        let before = text[..end].trim_end_matches(is_closer);
+        before.ends_with([',', ';', ':', '\u{2014}', '\u{2013}']) || before.ends_with(" -")
+    })
+}
+
+/// Split `text` at whitespace runs where `is_boundary` accepts the text
+/// ending at the start of the run and the text resuming after it.
+fn split_after(text: &str, is_boundary: impl Fn(&str, usize, usize) -> bool) -> Vec<&str> {
+    let text = text.trim();
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((ii, ch)) = chars.next() {
+        if !ch.is_whitespace() {
+            continue;
+        }
+        let end = ii;
+        while let Some((_, ch)) = chars.peek() {
+            if !ch.is_whitespace() {
+                break;
+            }
+            chars.next();
+        }
+        let next = chars.peek().map_or(text.len(), |(jj, _)| *jj);
+        if is_boundary(text, end, next) {
+            pieces.push(&text[start..end]);
+            start = next;
+        }
+    }
+    if start < text.len() {
+        pieces.push(&text[start..]);
+    }
+    pieces
+}
+
+fn is_sentence_end(text: &str, end: usize, next: usize) -> bool {
+    // A sentence never starts with a lowercase letter.
+    if text[next..]
+        .chars()
+        .find(|ch| !ssml::is_marker(*ch))
+        .is_some_and(char::is_lowercase)
+    {
+        return false;
+    }
+    let before = text[..end].trim_end_matches(is_closer);
+    if before.ends_with(['!', '?', '\u{2026}']) {
+        return true;
+    }
+    if !before.ends_with('.') || before.ends_with("..") && !before.ends_with("...") {
+        return false;
+    }
+    let word = before
+        .trim_end_matches('.')
+        .rsplit(|ch: char| ch.is_whitespace() || "([{\"'".contains(ch))
+        .next()
+        .unwrap_or("")
+        .trim_start_matches(ssml::is_marker);
+    let is_initial = word.chars().count() == 1 && word.chars().all(char::is_uppercase);
+    let is_abbreviation = ABBREVIATIONS.contains(&word.to_lowercase().as_str());
+    !(is_initial || is_abbreviation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn texts(chunks: Vec<Packed>) -> Vec<String> {
+        chunks.into_iter().map(|chunk| chunk.text).collect()
+    }
+
+    fn pack_config(segments: Vec<Segment>, config: &ChunkConfig) -> Vec<Packed> {
+        let measure = |text: &str| config.unit.measure(text);
+        let anchor = |current: &str, text: &str| {
+            config.stable && breaks_at_anchor(measure(current), text, measure(text), config.limit)
+        };
+        pack(segments, &|text| config.fits(text), &anchor)
+    }
+
+    #[test]
+    fn test_split_sentences() {
+        let text = "Dr. Smith paid $3.50 for it. J. R. R. Tolkien wrote e.g. this! \
+                    Really? \"Yes.\" The end";
+        assert_eq!(
+            split_sentences(text),
+            vec![
+                "Dr. Smith paid $3.50 for it.",
+                "J. R. R. Tolkien wrote e.g. this!",
+                "Really?",
+                "\"Yes.\"",
+                "The end",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pack_prefers_sentence_boundaries() {
+        let segments = vec![Segment::new(
+            "One two three. Four five six seven. Eight.",
+            Break::Block,
+        )];
+        assert_eq!(
+            texts(pack_config(segments, &ChunkConfig::new(20, Unit::Bytes))),
+            vec!["One two three.", "Four five six seven.", "Eight."]
+        );
+    }
+
+    #[test]
+    fn test_pack_prefers_paragraph_boundaries() {
+        let segments = vec![
+            Segment::new("Alpha beta.", Break::Block),
+            Segment::new("Gamma delta.", Break::Paragraph),
+            Segment::new("Epsilon.", Break::Paragraph),
+        ];
+        assert_eq!(
+            texts(pack_config(segments, &ChunkConfig::new(25, Unit::Bytes))),
+            vec!["Alpha beta.\n\nGamma delta.", "Epsilon."]
+        );
+    }
+
+    #[test]
+    fn test_pack_merges_only_sibling_sections() {
+        let segments = vec![
+            Segment::new("A", Break::Block),
+            Segment::new("B", Break::Section),
+            Segment::new("C", Break::Forced),
+            Segment::new("D", Break::Section),
+        ];
+        assert_eq!(
+            texts(pack_config(segments, &ChunkConfig::new(20, Unit::Bytes))),
+            vec!["A\n\nB", "C\n\nD"]
+        );
+    }
+
+    #[test]
+    fn test_pack_splits_long_sentence_at_clauses() {
+        let segments = vec![Segment::new(
+            "First clause here, second clause here; third.",
+            Break::Block,
+        )];
+        assert_eq!(
+            texts(pack_config(segments, &ChunkConfig::new(20, Unit::Bytes))),
+            vec!["First clause here,", "second clause here;", "third."]
+        );
+    }
+
+    #[test]
+    fn test_pack_splits_long_tokens() {
+        let segments = vec![Segment::new(
+            "See https://example.com/a/b/c now.",
+            Break::Block,
+        )];
+        assert_eq!(
+            texts(pack_config(segments, &ChunkConfig::new(10, Unit::Bytes))),
+            vec!["See", "https://ex", "ample.com/", "a/b/c", "now."]
+        );
+    }
+
+    fn segments() -> impl Strategy<Value = Vec<Segment>> {
+        let text = "[a-zA-Z0-9éß€😀.,;:!? -]{0,200}";
+        let break_before = prop_oneof![Just(Break::Paragraph), Just(Break::Block)];
+        prop::collection::vec((text, break_before), 0..8).prop_map(|blocks| {
+            blocks
+                .into_iter()
+                .map(|(text, break_before)| Segment::new(text, break_before))
+                .collect()
+        })
+    }
+
+    fn configs() -> impl Strategy<Value = ChunkConfig> {
+        let unit = prop_oneof![
+            Just(Unit::Chars),
+            Just(Unit::Bytes),
+            Just(Unit::Words),
+            Just(Unit::Tokens),
+        ];
+        (4usize..120, unit, any::<bool>()).prop_map(|(limit, unit, stable)| ChunkConfig {
+            stable,
+            ..ChunkConfig::new(limit, unit)
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn prop_chunks_never_exceed_limit(segments in segments(), config in configs()) {
+            texts(pack_config(segments, &config)).iter().for_each(|chunk| {
+                let length = config.unit.measure(chunk);
+                assert!(length <= config.limit, "{} > {}: {:?}", length, config.limit, chunk);
+            });
+        }
+
+        #[test]
+        fn prop_chunks_keep_all_text(segments in segments(), config in configs()) {
+            let strip = |text: &str| text.chars().filter(|ch| !ch.is_whitespace()).collect::<String>();
+            let expected: String = segments.iter().map(|segment| strip(&segment.text)).collect();
+            let actual: String = texts(pack_config(segments, &config)).iter().map(|chunk| strip(chunk)).collect();
+            prop_assert_eq!(expected, actual);
+        }
+    }
+}
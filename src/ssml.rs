@@ -0,0 +1,256 @@
+//! SSML rendering of chunk text.
+//!
+//! While chunking for SSML, inline structure is carried through the packer as
+//! private-use marker characters. Emphasis markers wrap every word rather than
+//! the whole span, so a chunk cut at any whitespace still holds balanced
+//! markers. [`render`] turns a packed chunk into a `<speak>` document.
+
+/// Markup of the chunk text.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Markup {
+    /// Plain text.
+    #[default]
+    Text,
+    /// One SSML `<speak>` document per chunk.
+    Ssml,
+}
+
+names!(Markup, "markup", {
+    Text => "text",
+    Ssml => "ssml",
+});
+
+const EMPHASIS_OPEN: char = '\u{E000}';
+const EMPHASIS_CLOSE: char = '\u{E001}';
+const STRONG_OPEN: char = '\u{E002}';
+const STRONG_CLOSE: char = '\u{E003}';
+/// Prefix of a heading block.
+pub const HEADING: char = '\u{E004}';
+/// Prefix of a list item block.
+pub const LIST_ITEM: char = '\u{E005}';
+const PHONEME_OPEN: char = '\u{E006}';
+const PHONEME_TEXT: char = '\u{E007}';
+const PHONEME_CLOSE: char = '\u{E008}';
+/// A space inside a phoneme span, kept apart from whitespace so the packer
+/// never splits the span.
+const PHONEME_SPACE: char = '\u{E009}';
+
+/// Whether `ch` is one of the markers used while chunking for SSML.
+pub fn is_marker(ch: char) -> bool {
+    ('\u{E000}'..='\u{E009}').contains(&ch)
+}
+
+/// Mark every word of `text` as emphasised, strongly if `strong` is set.
+pub fn emphasis(text: &str, strong: bool) -> String {
+    let (open, close) = if strong {
+        (STRONG_OPEN, STRONG_CLOSE)
+    } else {
+        (EMPHASIS_OPEN, EMPHASIS_CLOSE)
+    };
+    text.split_inclusive(char::is_whitespace)
+        .map(|piece| {
+            let word = piece.trim_end();
+            if word.is_empty() {
+                piece.to_string()
+            } else {
+                format!("{open}{word}{close}{}", &piece[word.len()..])
+            }
+        })
+        .collect()
+}
+
+/// Mark `text` to be pronounced as the IPA transcription `ipa`.
+pub fn phoneme(text: &str, ipa: &str) -> String {
+    let unspace = |text: &str| text.replace(' ', &PHONEME_SPACE.to_string());
+    format!(
+        "{PHONEME_OPEN}{}{PHONEME_TEXT}{}{PHONEME_CLOSE}",
+        unspace(ipa),
+        unspace(text)
+    )
+}
+
+/// Remove all markers and phoneme transcriptions, leaving the spoken text.
+pub fn strip(text: &str) -> String {
+    plain_phonemes(text)
+        .chars()
+        .filter(|ch| !is_marker(*ch))
+        .collect()
+}
+
+/// Replace each phoneme span with the text it marks.
+fn plain_phonemes(text: &str) -> String {
+    // A piece split from the middle of a transcription starts inside it.
+    let mut in_transcription = text
+        .chars()
+        .find(|ch| (PHONEME_OPEN..=PHONEME_CLOSE).contains(ch))
+        == Some(PHONEME_TEXT);
+    text.chars()
+        .filter_map(|ch| match ch {
+            PHONEME_OPEN => {
+                in_transcription = true;
+                None
+            }
+            PHONEME_TEXT => {
+                in_transcription = false;
+                None
+            }
+            PHONEME_CLOSE => None,
+            _ if in_transcription => None,
+            PHONEME_SPACE => Some(' '),
+            ch => Some(ch),
+        })
+        .collect()
+}
+
+/// Whether every phoneme span in `text` is complete, which fails only when a
+/// character-level split cut through one.
+fn has_whole_phonemes(text: &str) -> bool {
+    let markers: Vec<char> = text
+        .chars()
+        .filter(|ch| (PHONEME_OPEN..=PHONEME_CLOSE).contains(ch))
+        .collect();
+    markers
+        .chunks(3)
+        .all(|span| span == [PHONEME_OPEN, PHONEME_TEXT, PHONEME_CLOSE])
+}
+
+/// Render packed chunk text as an SSML document. Blocks are separated by blank
+/// lines: headings become a pause and strong emphasis, list items sentences
+/// followed by a pause, and everything else a paragraph.
+pub fn render(text: &str) -> String {
+    let body: String = text
+        .split("\n\n")
+        .map(|block| {
+            if let Some(heading) = block.strip_prefix(HEADING) {
+                format!(
+                    "<break strength=\"strong\"/><emphasis level=\"strong\">{}</emphasis>\
+                     <break strength=\"medium\"/>",
+                    escape(&strip(heading))
+                )
+            } else if let Some(item) = block.strip_prefix(LIST_ITEM) {
+                format!("<s>{}</s><break strength=\"medium\"/>", inline(item))
+            } else {
+                format!("<p>{}</p>", inline(block))
+            }
+        })
+        .collect();
+    format!("<speak>{body}</speak>")
+}
+
+/// Escape a block and replace its emphasis and phoneme markers with tags,
+/// closing any emphasis a character-level split left open and reading any
+/// phoneme it cut through as plain text.
+fn inline(block: &str) -> String {
+    let tag = |ch: char| match ch {
+        EMPHASIS_OPEN => "<emphasis level=\"moderate\">",
+        STRONG_OPEN => "<emphasis level=\"strong\">",
+        EMPHASIS_CLOSE | STRONG_CLOSE => "</emphasis>",
+        PHONEME_OPEN => "<phoneme alphabet=\"ipa\" ph=\"",
+        PHONEME_TEXT => "\">",
+        PHONEME_CLOSE => "</phoneme>",
+        PHONEME_SPACE => " ",
+        _ => "",
+    };
+    let block = match has_whole_phonemes(block) {
+        true => block.to_string(),
+        false => plain_phonemes(block),
+    };
+    let (mut text, open) = escape(&block).chars().fold(
+        (String::new(), None),
+        |(mut text, open): (String, Option<char>), ch| match ch {
+            EMPHASIS_OPEN | STRONG_OPEN => {
+                text.push_str(tag(ch));
+                (text, Some(ch))
+            }
+            EMPHASIS_CLOSE | STRONG_CLOSE if open.is_none() => {
+                let reopen = if ch == STRONG_CLOSE {
+                    STRONG_OPEN
+                } else {
+                    EMPHASIS_OPEN
+                };
+                (format!("{}{text}{}", tag(reopen), tag(ch)), None)
+            }
+            EMPHASIS_CLOSE | STRONG_CLOSE => {
+                text.push_str(tag(ch));
+                (text, None)
+            }
+            PHONEME_OPEN..=PHONEME_SPACE => {
+                text.push_str(tag(ch));
+                (text, open)
+            }
+            ch if is_marker(ch) => (text, open),
+            ch => {
+                text.push(ch);
+                (text, open)
+            }
+        },
+    );
+    if let Some(open) = open {
+        text.push_str(tag(if open == STRONG_OPEN {
+            STRONG_CLOSE
+        } else {
+            EMPHASIS_CLOSE
+        }));
+    }
+    [tag(EMPHASIS_OPEN), tag(STRONG_OPEN)]
+        .iter()
+        .fold(text, |text, open| {
+            text.replace(&format!("</emphasis> {open}"), " ")
+        })
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let text = format!(
+            "{HEADING}Intro\n\nSay {} & go.\n\n{LIST_ITEM}One.",
+            emphasis("it loud", false)
+        );
+        assert_eq!(
+            render(&text),
+            "<speak><break strength=\"strong\"/><emphasis level=\"strong\">Intro</emphasis>\
+             <break strength=\"medium\"/><p>Say <emphasis level=\"moderate\">it loud</emphasis> \
+             &amp; go.</p><s>One.</s><break strength=\"medium\"/></speak>"
+        );
+    }
+
+    #[test]
+    fn test_phoneme() {
+        let text = format!("Ask {} today.", phoneme("Nguyen Le", "ŋwiən le"));
+        assert_eq!(strip(&text), "Ask Nguyen Le today.");
+        assert_eq!(
+            render(&text),
+            "<speak><p>Ask <phoneme alphabet=\"ipa\" ph=\"ŋwiən le\">Nguyen Le</phoneme> \
+             today.</p></speak>"
+        );
+        let (head, tail) = text.split_at(text.find('w').unwrap());
+        assert_eq!(render(head), "<speak><p>Ask </p></speak>");
+        assert_eq!(render(tail), "<speak><p>Nguyen Le today.</p></speak>");
+    }
+
+    #[test]
+    fn test_render_balances_split_words() {
+        let text = emphasis("unbreakable", true);
+        let (head, tail) = text.split_at(STRONG_OPEN.len_utf8() + 6);
+        assert_eq!(
+            render(head),
+            "<speak><p><emphasis level=\"strong\">unbrea</emphasis></p></speak>"
+        );
+        assert_eq!(
+            render(tail),
+            "<speak><p><emphasis level=\"strong\">kable</emphasis></p></speak>"
+        );
+    }
+}
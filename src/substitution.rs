@@ -0,0 +1,1046 @@
+//! Pronunciation substitutions loaded from a CSV dictionary.
+//!
+//! A dictionary starts with a header row naming its columns, in any order:
+//!
+//! | Column        | Contents                                                    |
+//! |---------------|-------------------------------------------------------------|
+//! | `pattern`     | Text to find. Required.                                     |
+//! | `replacement` | Text to speak instead. Required.                            |
+//! | `kind`        | `literal` (default), `regex` or `phonetic`.                 |
+//! | `case`        | `sensitive` (default), `insensitive` or `preserve`.         |
+//! | `boundary`    | `word` (default), `token` or `none`.                        |
+//! | `scope`       | Where the rule applies. Default `prose heading`.            |
+//! | `note`        | Free text for the dictionary's maintainers.                 |
+//! | `flags`       | Single-letter shorthand: `i`, `p`, `t`, `s` and `r`.        |
+//!
+//! Empty cells take the default. A scope is any of `prose`, `code`,
+//! `heading` and `link`, separated by spaces or `|`. A regex rule's
+//! replacement may refer to capture groups as `$1`, and a phonetic rule's
+//! replacement is an IPA transcription, spoken only in SSML output.
+//!
+//! The first row is always taken as the header; one that does not name a
+//! `pattern` column, such as an older `from,to` header, is skipped with a
+//! warning and the columns are read as `pattern,replacement,flags`. A row
+//! with fewer than two fields, or more fields than there are columns, is an
+//! error; a row missing only optional columns gives a warning.
+
+use crate::error::Error;
+use crate::ssml::{self, Markup};
+use aho_corasick::{AhoCorasick, AhoCorasickBuilder};
+use csv::{ReaderBuilder, StringRecord};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// How a substitution's pattern and replacement are read.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    /// Find the pattern as written.
+    #[default]
+    Literal,
+    /// The pattern is a regular expression and the replacement may refer to
+    /// its capture groups.
+    Regex,
+    /// The replacement is an IPA transcription of the pattern.
+    Phonetic,
+}
+
+names!(Kind, "kind", {
+    Literal => "literal",
+    Regex => "regex",
+    Phonetic => "phonetic",
+});
+
+/// How a substitution's pattern is matched against letter case.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum Case {
+    /// Match the pattern's case exactly.
+    #[default]
+    Sensitive,
+    /// Match any case and insert the replacement as written.
+    Insensitive,
+    /// Match any case and give the replacement the case of the matched text:
+    /// lowercase, capitalised or all caps.
+    Preserve,
+}
+
+names!(Case, "case", {
+    Sensitive => "sensitive",
+    Insensitive => "insensitive",
+    Preserve => "preserve",
+});
+
+/// Which neighbouring characters may surround a match.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum Boundary {
+    /// The match may not continue a word, so "AI" does not match in "SAID".
+    #[default]
+    Word,
+    /// The match must be a whole token, where hyphens and apostrophes join
+    /// words, so "AI" does not match in "AI-powered".
+    Token,
+    /// Match anywhere, even inside words.
+    None,
+}
+
+names!(Boundary, "boundary", {
+    Word => "word",
+    Token => "token",
+    None => "none",
+});
+
+/// When substitutions are applied.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum SubstitutionMode {
+    /// Rewrite the markdown source before it is parsed, ignoring rule
+    /// scopes. Chunk positions are mapped back to the original source.
+    #[default]
+    Source,
+    /// Rewrite the text of parsed nodes in each rule's scopes, leaving the
+    /// markdown structure and front matter intact.
+    Tree,
+}
+
+names!(SubstitutionMode, "substitution mode", {
+    Source => "source",
+    Tree => "tree",
+});
+
+/// A kind of document text a substitution may apply to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Scope {
+    /// Paragraphs, list items, tables and link text.
+    Prose,
+    /// Code blocks and inline code.
+    Code,
+    Heading,
+    /// Link destinations, when they are read out.
+    Link,
+}
+
+names!(Scope, "scope", {
+    Prose => "prose",
+    Code => "code",
+    Heading => "heading",
+    Link => "link",
+});
+
+/// A single pronunciation rule.
+#[derive(Clone, Debug)]
+pub struct Substitution {
+    pattern: String,
+    replacement: String,
+    kind: Kind,
+    case: Case,
+    boundary: Boundary,
+    scopes: Vec<Scope>,
+    note: Option<String>,
+    /// Name of the dictionary the rule came from.
+    layer: Option<String>,
+    /// The compiled pattern of a regex rule.
+    regex: Option<Regex>,
+}
+
+impl Substitution {
+    /// A case-sensitive literal rule matching on word boundaries in prose
+    /// and headings.
+    pub fn new(pattern: impl Into<String>, replacement: impl Into<String>) -> Self {
+        Substitution {
+            pattern: pattern.into(),
+            replacement: replacement.into(),
+            kind: Kind::Literal,
+            case: Case::Sensitive,
+            boundary: Boundary::Word,
+            scopes: vec![Scope::Prose, Scope::Heading],
+            note: None,
+            layer: None,
+            regex: None,
+        }
+    }
+
+    /// A rule pronouncing `pattern` as the IPA transcription `ipa`.
+    pub fn phonetic(pattern: impl Into<String>, ipa: impl Into<String>) -> Self {
+        Substitution {
+            kind: Kind::Phonetic,
+            ..Substitution::new(pattern, ipa)
+        }
+    }
+
+    /// A rule whose pattern is a regular expression. The replacement may refer
+    /// to capture groups as `$1`, `${1}` or `$name`.
+    pub fn regex(
+        pattern: impl Into<String>,
+        replacement: impl Into<String>,
+    ) -> Result<Self, regex::Error> {
+        Substitution::new(pattern, replacement).into_regex()
+    }
+
+    fn into_regex(mut self) -> Result<Self, regex::Error> {
+        self.regex = Some(build_regex(&self.pattern, self.case)?);
+        self.kind = Kind::Regex;
+        Ok(self)
+    }
+
+    /// Give the rule `kind`, failing if it is a regex that does not compile.
+    fn with_kind(self, kind: Kind) -> Result<Self, regex::Error> {
+        match kind {
+            Kind::Regex => self.into_regex(),
+            kind => Ok(Substitution {
+                kind,
+                regex: None,
+                ..self
+            }),
+        }
+    }
+
+    /// Whether the pattern matches regardless of case.
+    pub fn case(mut self, case: Case) -> Self {
+        if self.regex.is_some() {
+            self.regex = Some(build_regex(&self.pattern, case).expect("pattern compiled before"));
+        }
+        self.case = case;
+        self
+    }
+
+    /// Where in a word the pattern may match.
+    pub fn boundary(mut self, boundary: Boundary) -> Self {
+        self.boundary = boundary;
+        self
+    }
+
+    /// The kinds of text the rule applies to.
+    pub fn scopes(mut self, scopes: impl IntoIterator<Item = Scope>) -> Self {
+        self.scopes = scopes.into_iter().collect();
+        self.scopes.sort();
+        self.scopes.dedup();
+        self
+    }
+
+    /// The pattern as written in the dictionary.
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// The replacement text, or IPA for a phonetic rule.
+    pub fn replacement(&self) -> &str {
+        &self.replacement
+    }
+
+    /// How the pattern and replacement are read.
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    /// Whether the rule rewrites text of `scope`.
+    pub fn applies_to(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope)
+    }
+
+    /// The dictionary the rule was loaded from, such as its path.
+    pub fn layer(&self) -> Option<&str> {
+        self.layer.as_deref()
+    }
+
+    /// The dictionary's note on the rule.
+    pub fn note(&self) -> Option<&str> {
+        self.note.as_deref()
+    }
+
+    /// Apply the flags in a dictionary's flags column: `i` matches any case,
+    /// `p` also preserves the matched case, `t` matches whole tokens, `s`
+    /// matches anywhere and `r` reads the pattern as a regular expression.
+    fn flags(self, flags: &str) -> Result<Self, String> {
+        flags
+            .chars()
+            .filter(|ch| !ch.is_whitespace())
+            .try_fold(self, |rule, flag| {
+                Ok(match flag {
+                    'i' => rule.case(Case::Insensitive),
+                    'p' => rule.case(Case::Preserve),
+                    't' => rule.boundary(Boundary::Token),
+                    's' => rule.boundary(Boundary::None),
+                    'r' => rule
+                        .into_regex()
+                        .map_err(|error| format!("invalid regex: {error}"))?,
+                    flag => return Err(format!("unknown substitution flag {flag:?}")),
+                })
+            })
+    }
+
+    /// Replace every match of the pattern in `text`.
+    pub fn apply(&self, text: &str) -> String {
+        let mut table = SubstitutionTable::new();
+        table.insert_rule(self.clone());
+        table.apply(text)
+    }
+
+    /// The strings searched for. The automaton folds ASCII case only, so a
+    /// case-insensitive pattern with other letters is also searched for in
+    /// lowercase, capitalised and all caps.
+    fn variants(&self) -> Vec<String> {
+        if self.regex.is_some() {
+            return Vec::new();
+        }
+        let mut variants = vec![self.pattern.clone()];
+        if self.case != Case::Sensitive && !self.pattern.is_ascii() {
+            let lower = self.pattern.to_lowercase();
+            variants.push(capitalize(&lower));
+            variants.push(self.pattern.to_uppercase());
+            variants.push(lower);
+            variants.sort();
+            variants.dedup();
+        }
+        variants
+    }
+
+    /// Whether `text[start..end]`, found case-insensitively, is a match.
+    fn accepts(&self, text: &str, start: usize, end: usize) -> bool {
+        (self.case != Case::Sensitive || text[start..end] == self.pattern)
+            && self.is_bounded(text, start, end)
+    }
+
+    /// Whether the characters around `text[start..end]` satisfy the boundary.
+    fn is_bounded(&self, text: &str, start: usize, end: usize) -> bool {
+        let joins: fn(char) -> bool = match self.boundary {
+            Boundary::None => return true,
+            Boundary::Word => is_word_char,
+            Boundary::Token => |ch| is_word_char(ch) || ch == '-' || ch == '\'' || ch == '\u{2019}',
+        };
+        let matched = &text[start..end];
+        let before = text[..start].chars().next_back();
+        let after = text[end..].chars().next();
+        let first = matched.chars().next();
+        let last = matched.chars().next_back();
+        let continues = |outside: Option<char>, edge: Option<char>| {
+            outside.is_some_and(joins) && edge.is_some_and(joins)
+        };
+        !continues(before, first) && !continues(after, last)
+    }
+
+    fn replacement_for(&self, matched: &str, replacement: &str) -> String {
+        match (self.kind, self.case) {
+            (Kind::Phonetic, _) => ssml::phoneme(matched, replacement),
+            (_, Case::Preserve) => match_case(matched, replacement),
+            (_, Case::Sensitive | Case::Insensitive) => replacement.to_string(),
+        }
+    }
+}
+
+fn is_word_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+fn build_regex(pattern: &str, case: Case) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(case != Case::Sensitive)
+        .build()
+}
+
+/// Give `replacement` the case of `matched`.
+fn match_case(matched: &str, replacement: &str) -> String {
+    let letters: Vec<char> = matched.chars().filter(|ch| ch.is_alphabetic()).collect();
+    if letters.len() > 1 && letters.iter().all(|ch| ch.is_uppercase()) {
+        replacement.to_uppercase()
+    } else if letters.first().is_some_and(|ch| ch.is_uppercase()) {
+        capitalize(replacement)
+    } else if letters.iter().all(|ch| ch.is_lowercase()) {
+        replacement.to_lowercase()
+    } else {
+        replacement.to_string()
+    }
+}
+
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .map(|first| first.to_uppercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+/// A substitution made in a text.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[non_exhaustive]
+pub struct Hit {
+    /// Pattern of the rule that matched.
+    pub pattern: String,
+    /// Dictionary the rule came from.
+    pub layer: Option<String>,
+    /// The text that was replaced.
+    pub matched: String,
+    /// The text it was replaced with.
+    pub replacement: String,
+    /// One-based line of the match.
+    pub line: usize,
+    /// One-based column of the match, in characters.
+    pub column: usize,
+}
+
+/// A set of pronunciation rules applied to a document before it is chunked.
+///
+/// All rules are matched against the original text. Where matches overlap, the
+/// leftmost wins, then the longest, then a case-sensitive rule, then the rule
+/// inserted first, so "REST API" takes precedence over "API" and replacement
+/// text is never substituted again.
+#[derive(Clone, Debug, Default)]
+pub struct SubstitutionTable {
+    rules: Vec<Substitution>,
+    positions: HashMap<String, usize>,
+    matcher: OnceLock<Matcher>,
+    warnings: Vec<String>,
+}
+
+/// An automaton over every rule's search strings.
+#[derive(Clone, Debug)]
+struct Matcher {
+    automaton: AhoCorasick,
+    /// The rule each automaton pattern belongs to.
+    rules: Vec<usize>,
+}
+
+impl Matcher {
+    fn new(rules: &[Substitution]) -> Self {
+        let (patterns, rules): (Vec<String>, Vec<usize>) = rules
+            .iter()
+            .enumerate()
+            .flat_map(|(ii, rule)| {
+                rule.variants()
+                    .into_iter()
+                    .map(move |variant| (variant, ii))
+            })
+            .unzip();
+        let automaton = AhoCorasickBuilder::new()
+            .ascii_case_insensitive(true)
+            .build(&patterns)
+            .expect("substitution dictionary fits in an automaton");
+        Matcher { automaton, rules }
+    }
+}
+
+impl SubstitutionTable {
+    /// An empty table.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read a CSV dictionary: a header row naming the `pattern` and
+    /// `replacement` columns and any of `kind`, `case`, `boundary`, `scope`,
+    /// `note` and `flags`, then one rule per row. Its rules are labelled with
+    /// the path, and its errors and warnings start with it.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|error| Error::io(path, error))?;
+        let mut table = Self::from_reader(file).map_err(|error| error.at(path.display()))?;
+        table
+            .warnings
+            .iter_mut()
+            .for_each(|warning| *warning = format!("{}: {warning}", path.display()));
+        Ok(table.layer(path.display().to_string()))
+    }
+
+    /// Read every `.csv` dictionary in a directory, in file name order, so
+    /// that later files override earlier ones. A missing directory gives an
+    /// empty table.
+    pub fn from_dir(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let mut table = Self::new();
+        let entries = match fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(table),
+            Err(error) => return Err(Error::io(path, error)),
+        };
+        let mut paths = entries
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|error| Error::io(path, error))?;
+        paths.retain(|path| path.extension().is_some_and(|extension| extension == "csv"));
+        paths.sort();
+        paths.iter().try_for_each(|path| {
+            table.merge(Self::from_path(path)?);
+            Ok::<(), Error>(())
+        })?;
+        Ok(table)
+    }
+
+    /// Read a CSV dictionary from `reader`.
+    pub fn from_reader(reader: impl io::Read) -> Result<Self, Error> {
+        let mut rdr = ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(reader);
+        let mut table = Self::new();
+        let mut columns = None;
+
+        rdr.records().try_for_each(|result| {
+            let record = result.map_err(|error| Error::parse("CSV", error))?;
+            let line = record.position().map_or(0, |position| position.line());
+            let columns = match columns {
+                Some(ref columns) => columns,
+                None => match Columns::from_header(&record)
+                    .map_err(|error| Error::parse(format!("line {line}"), error))?
+                {
+                    Some(header) => {
+                        columns = Some(header);
+                        return Ok(());
+                    }
+                    None => {
+                        let header: Vec<&str> = record.iter().collect();
+                        table.warnings.push(format!(
+                            "line {line}: header {:?} has no pattern column; \
+                             reading columns as pattern, replacement, flags",
+                            header.join(",")
+                        ));
+                        columns = Some(Columns::default());
+                        return Ok(());
+                    }
+                },
+            };
+            if record.iter().all(|field| field.trim().is_empty()) {
+                return Ok(());
+            }
+            if record.len() < columns.required() || record.len() > columns.len {
+                return Err(Error::parse(
+                    format!("line {line}"),
+                    format!("expected {} fields, found {}", columns.len, record.len()),
+                ));
+            }
+            if columns.named && record.len() < columns.len {
+                table.warnings.push(format!(
+                    "line {line}: expected {} fields, found {}",
+                    columns.len,
+                    record.len()
+                ));
+            }
+            let rule = columns
+                .rule(&record)
+                .map_err(|error| Error::parse(format!("line {line}"), error))?;
+            table.insert_rule(rule);
+            Ok::<(), Error>(())
+        })?;
+
+        Ok(table)
+    }
+
+    /// Add a case-sensitive word-boundary substitution, replacing any earlier
+    /// one for the same pattern.
+    pub fn insert(&mut self, pattern: impl Into<String>, replacement: impl Into<String>) {
+        self.insert_rule(Substitution::new(pattern, replacement));
+    }
+
+    /// Add a rule, replacing any earlier one for the same pattern in place.
+    /// Rules with an empty pattern are ignored.
+    pub fn insert_rule(&mut self, rule: Substitution) {
+        if rule.pattern.is_empty() {
+            return;
+        }
+        match self.positions.get(&rule.pattern) {
+            Some(&position) => self.rules[position] = rule,
+            None => {
+                self.positions
+                    .insert(rule.pattern.clone(), self.rules.len());
+                self.rules.push(rule);
+            }
+        }
+        self.matcher = OnceLock::new();
+    }
+
+    /// Add the rules and warnings of `other`, whose rules override any for
+    /// the same patterns.
+    pub fn merge(&mut self, other: SubstitutionTable) {
+        other
+            .rules
+            .into_iter()
+            .for_each(|rule| self.insert_rule(rule));
+        self.warnings.extend(other.warnings);
+    }
+
+    /// Label every rule as coming from the dictionary `layer`.
+    pub fn layer(mut self, layer: impl Into<String>) -> Self {
+        let layer = layer.into();
+        self.rules
+            .iter_mut()
+            .for_each(|rule| rule.layer = Some(layer.clone()));
+        self
+    }
+
+    /// The rules in the order they were first inserted.
+    pub fn iter(&self) -> std::slice::Iter<'_, Substitution> {
+        self.rules.iter()
+    }
+
+    /// Number of rules.
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// Whether the table has no rules.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Problems in the dictionary that did not stop it loading, each naming
+    /// its line.
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// Replace every match of each pattern in `content`. Phonetic rules are
+    /// skipped, as plain text has no way to carry a transcription.
+    pub fn apply(&self, content: &str) -> String {
+        self.apply_markup(content, Markup::Text)
+    }
+
+    /// Replace every match of each pattern in `content`, marking the matches
+    /// of phonetic rules for SSML rendering when `markup` is SSML.
+    pub fn apply_markup(&self, content: &str, markup: Markup) -> String {
+        self.substitute(content, None, markup).0
+    }
+
+    /// Replace every match in `content` of the rules that apply to text in
+    /// `scope`, or of all rules if it is `None`, and list the replacements
+    /// made.
+    pub fn substitute(
+        &self,
+        content: &str,
+        scope: Option<Scope>,
+        markup: Markup,
+    ) -> (String, Vec<Hit>) {
+        let (result, hits, _) = self.substitute_with_edits(content, scope, markup);
+        (result, hits)
+    }
+
+    /// [`substitute`](Self::substitute), also returning where each
+    /// replacement was made, in order.
+    pub(crate) fn substitute_with_edits(
+        &self,
+        content: &str,
+        scope: Option<Scope>,
+        markup: Markup,
+    ) -> (String, Vec<Hit>, Vec<Edit>) {
+        let in_scope = |rule: &Substitution| scope.is_none_or(|scope| rule.applies_to(scope));
+        if !self.rules.iter().any(in_scope) {
+            return (content.to_string(), Vec::new(), Vec::new());
+        }
+        let matcher = self.matcher.get_or_init(|| Matcher::new(&self.rules));
+        let literals = matcher
+            .automaton
+            .find_overlapping_iter(content)
+            .map(|found| (found.start(), found.end(), matcher.rules[found.pattern()]))
+            .filter(|&(start, end, rule)| {
+                let rule = &self.rules[rule];
+                in_scope(rule)
+                    && (rule.kind != Kind::Phonetic || markup == Markup::Ssml)
+                    && rule.accepts(content, start, end)
+            })
+            .map(|(start, end, rule)| {
+                let replacement = &self.rules[rule].replacement;
+                (start, end, rule, replacement.clone())
+            });
+        let regexes = self
+            .rules
+            .iter()
+            .enumerate()
+            .filter(|(_, substitution)| in_scope(substitution))
+            .flat_map(|(rule, substitution)| {
+                let regex = substitution.regex.iter();
+                regex.flat_map(move |regex| {
+                    regex.captures_iter(content).filter_map(move |caps| {
+                        let found = caps.get(0)?;
+                        if found.is_empty()
+                            || !substitution.is_bounded(content, found.start(), found.end())
+                        {
+                            return None;
+                        }
+                        let mut replacement = String::new();
+                        caps.expand(&substitution.replacement, &mut replacement);
+                        Some((found.start(), found.end(), rule, replacement))
+                    })
+                })
+            });
+        let mut matches: Vec<(usize, usize, usize, String)> = literals.chain(regexes).collect();
+        matches.sort_by_key(|&(start, end, rule, _)| {
+            let is_sensitive = self.rules[rule].case == Case::Sensitive;
+            (start, Reverse(end), !is_sensitive, rule)
+        });
+
+        let mut hits = Vec::new();
+        let mut edits = Vec::new();
+        let mut line = 1;
+        let mut line_start = 0;
+        let (mut result, last) = matches.into_iter().fold(
+            (String::new(), 0),
+            |(mut result, last), (start, end, rule, replacement)| {
+                if start < last {
+                    return (result, last);
+                }
+                let matched = &content[start..end];
+                let rule = &self.rules[rule];
+                result.push_str(&content[last..start]);
+                let from = result.len();
+                result.push_str(&rule.replacement_for(matched, &replacement));
+                edits.push(Edit {
+                    original: start..end,
+                    substituted: from..result.len(),
+                });
+
+                let skipped = &content[last..start];
+                if let Some(newline) = skipped.rfind('\n') {
+                    line += skipped.matches('\n').count();
+                    line_start = last + newline + 1;
+                }
+                hits.push(Hit {
+                    pattern: rule.pattern.clone(),
+                    layer: rule.layer.clone(),
+                    matched: matched.to_string(),
+                    replacement,
+                    line,
+                    column: content[line_start..start].chars().count() + 1,
+                });
+                line += matched.matches('\n').count();
+                if let Some(newline) = matched.rfind('\n') {
+                    line_start = start + newline + 1;
+                }
+                (result, end)
+            },
+        );
+        result.push_str(&content[last..]);
+        (result, hits, edits)
+    }
+}
+
+/// A replacement made by [`SubstitutionTable::substitute_with_edits`]: the
+/// byte range it replaced in the original text and the range of its
+/// replacement in the result.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Edit {
+    pub original: Range<usize>,
+    pub substituted: Range<usize>,
+}
+
+/// The offset in the original text of `offset` in the substituted text. An
+/// offset inside a replacement is moved to the start of what it replaced, or
+/// to the end if `end` is set, as for the exclusive end of a range.
+pub(crate) fn original_offset(edits: &[Edit], offset: usize, end: bool) -> usize {
+    let before = edits.partition_point(|edit| match end {
+        true => edit.substituted.start < offset,
+        false => edit.substituted.start <= offset,
+    });
+    let Some(edit) = before.checked_sub(1).map(|index| &edits[index]) else {
+        return offset;
+    };
+    match (end, offset < edit.substituted.end) {
+        (false, true) => edit.original.start,
+        (true, _) if offset <= edit.substituted.end => edit.original.end,
+        _ => offset - edit.substituted.end + edit.original.end,
+    }
+}
+
+/// Positions of the columns of a dictionary.
+#[derive(Clone, Debug)]
+struct Columns {
+    pattern: usize,
+    replacement: usize,
+    kind: Option<usize>,
+    case: Option<usize>,
+    boundary: Option<usize>,
+    scope: Option<usize>,
+    note: Option<usize>,
+    flags: Option<usize>,
+    /// Number of columns.
+    len: usize,
+    /// Whether the columns were named by a header, so that every row should
+    /// fill them all.
+    named: bool,
+}
+
+/// The columns of a dictionary without a header.
+impl Default for Columns {
+    fn default() -> Self {
+        Columns {
+            pattern: 0,
+            replacement: 1,
+            kind: None,
+            case: None,
+            boundary: None,
+            scope: None,
+            note: None,
+            flags: Some(2),
+            len: 3,
+            named: false,
+        }
+    }
+}
+
+impl Columns {
+    /// The columns named by a header row, or `None` if `record` has no
+    /// `pattern` column and so is not a header.
+    fn from_header(record: &StringRecord) -> Result<Option<Self>, String> {
+        let names: Vec<String> = record
+            .iter()
+            .map(|name| name.trim().to_lowercase())
+            .collect();
+        let find = |column: &str| names.iter().position(|name| name == column);
+        let Some(pattern) = find("pattern") else {
+            return Ok(None);
+        };
+        let replacement = find("replacement").ok_or("header has no replacement column")?;
+        let known = [
+            "pattern",
+            "replacement",
+            "kind",
+            "case",
+            "boundary",
+            "scope",
+            "note",
+            "flags",
+        ];
+        if let Some(name) = names.iter().find(|name| !known.contains(&name.as_str())) {
+            return Err(format!("unknown column {name:?}"));
+        }
+        Ok(Some(Columns {
+            pattern,
+            replacement,
+            kind: find("kind"),
+            case: find("case"),
+            boundary: find("boundary"),
+            scope: find("scope"),
+            note: find("note"),
+            flags: find("flags"),
+            len: names.len(),
+            named: true,
+        }))
+    }
+
+    /// Number of fields a row needs to hold the pattern and replacement.
+    fn required(&self) -> usize {
+        self.pattern.max(self.replacement) + 1
+    }
+
+    /// The rule in a data row.
+    fn rule(&self, record: &StringRecord) -> Result<Substitution, String> {
+        let field = |column: Option<usize>| {
+            column
+                .and_then(|column| record.get(column))
+                .map(str::trim)
+                .filter(|field| !field.is_empty())
+        };
+        let mut rule = Substitution::new(&record[self.pattern], &record[self.replacement]);
+        if let Some(kind) = field(self.kind) {
+            rule = rule
+                .with_kind(kind.parse()?)
+                .map_err(|error| format!("invalid regex: {error}"))?;
+        }
+        if let Some(case) = field(self.case) {
+            rule = rule.case(case.parse()?);
+        }
+        if let Some(boundary) = field(self.boundary) {
+            rule = rule.boundary(boundary.parse()?);
+        }
+        if let Some(scope) = field(self.scope) {
+            let scopes = scope
+                .split(|ch: char| ch == '|' || ch.is_whitespace())
+                .filter(|scope| !scope.is_empty())
+                .map(str::parse)
+                .collect::<Result<Vec<Scope>, _>>()?;
+            rule = rule.scopes(scopes);
+        }
+        if let Some(note) = field(self.note) {
+            rule.note = Some(note.to_string());
+        }
+        match field(self.flags) {
+            Some(flags) => rule.flags(flags),
+            None => Ok(rule),
+        }
+    }
+}
+
+impl<P: Into<String>, R: Into<String>> FromIterator<(P, R)> for SubstitutionTable {
+    fn from_iter<I: IntoIterator<Item = (P, R)>>(iter: I) -> Self {
+        let mut table = Self::new();
+        iter.into_iter()
+            .for_each(|(pattern, replacement)| table.insert(pattern, replacement));
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_reader() {
+        let csv = "pattern,replacement,flags\nTTS,text to speech\nrust,Rust language,p\n";
+        let table = SubstitutionTable::from_reader(csv.as_bytes()).unwrap();
+        assert_eq!(table.len(), 2);
+        assert_eq!(
+            table.apply("TTS and rust, Rust, RUST"),
+            "text to speech and rust language, Rust language, RUST LANGUAGE"
+        );
+        assert!(SubstitutionTable::from_reader("a,b\nx,y,q\n".as_bytes()).is_err());
+
+        let old =
+            SubstitutionTable::from_reader("from,to\nTTS,text to speech\n".as_bytes()).unwrap();
+        assert_eq!(old.len(), 1);
+        assert_eq!(old.apply("TTS"), "text to speech");
+        assert_eq!(
+            old.warnings(),
+            ["line 1: header \"from,to\" has no pattern column; \
+              reading columns as pattern, replacement, flags"]
+        );
+    }
+
+    #[test]
+    fn test_from_reader_columns() {
+        let csv = "Pattern,Replacement,Kind,Case,Scope,Note\n\
+                   SQL,sequel,,insensitive,prose|heading,acronym\n\
+                   Nguyen,ŋwiən,phonetic,,prose\n\
+                   kubectl,cube control,,,code,\n\
+                   GUI,gooey\n";
+        let table = SubstitutionTable::from_reader(csv.as_bytes()).unwrap();
+        assert_eq!(table.len(), 4);
+        assert_eq!(
+            table.warnings(),
+            [
+                "line 3: expected 6 fields, found 5",
+                "line 5: expected 6 fields, found 2"
+            ]
+        );
+        assert_eq!(table.apply("sql and Nguyen"), "sequel and Nguyen");
+        assert_eq!(
+            table.apply_markup("Nguyen", Markup::Ssml),
+            ssml::phoneme("Nguyen", "ŋwiən")
+        );
+        let kubectl = &table.rules[table.positions["kubectl"]];
+        assert!(kubectl.applies_to(Scope::Code) && !kubectl.applies_to(Scope::Prose));
+
+        let error = |csv: &str| {
+            SubstitutionTable::from_reader(csv.as_bytes())
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            error("pattern,replacement\nA,B\nC,D,E\n"),
+            "line 3: expected 2 fields, found 3"
+        );
+        assert_eq!(
+            error("note,pattern,replacement\nx,A\n"),
+            "line 2: expected 3 fields, found 2"
+        );
+        assert!(error("pattern,replacement,case\nA,B,loud\n").starts_with("line 2: "));
+        assert_eq!(
+            error("pattern,replacment\n"),
+            "line 1: header has no replacement column"
+        );
+    }
+
+    #[test]
+    fn test_layers() {
+        let dir = std::env::temp_dir().join(format!("listnr-dictionaries-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("10-company.csv"),
+            "pattern,replacement\nAPI,A P I\nSQL,S Q L\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("20-product.csv"),
+            "pattern,replacement\nSQL,sequel\n",
+        )
+        .unwrap();
+        fs::write(dir.join("notes.txt"), "not,a dictionary\n").unwrap();
+
+        let mut table = SubstitutionTable::from_dir(&dir).unwrap();
+        let article: SubstitutionTable = [("API", "the interface")].into_iter().collect();
+        table.merge(article.layer("article"));
+        let (text, hits) = table.substitute("SQL and\nthe API", None, Markup::Text);
+        assert_eq!(text, "sequel and\nthe the interface");
+        let product = dir.join("20-product.csv").display().to_string();
+        let layers: Vec<_> = hits
+            .iter()
+            .map(|hit| (hit.layer.as_deref(), hit.line, hit.column))
+            .collect();
+        assert_eq!(
+            layers,
+            [(Some(product.as_str()), 1, 1), (Some("article"), 2, 5)]
+        );
+        assert!(SubstitutionTable::from_dir(dir.join("missing"))
+            .unwrap()
+            .is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_regex_rules() {
+        let csv = "pattern,replacement,flags\n\
+                   v(\\d+)\\.(\\d+),version $1 point $2,r\n\
+                   #(\\d+),issue number $1,rs\n\
+                   \"\\b([A-Z]{2,})s\\b\",${1} s,r\n\
+                   API,A P I\n";
+        let table = SubstitutionTable::from_reader(csv.as_bytes()).unwrap();
+        assert_eq!(
+            table.apply("Fixed in v2.10, see #42. The APIs and URLs changed."),
+            "Fixed in version 2 point 10, see issue number 42. The API s and URL s changed."
+        );
+
+        let error =
+            SubstitutionTable::from_reader("a,b\nok,fine\n(unclosed,x,r\n".as_bytes()).unwrap_err();
+        assert!(error.to_string().starts_with("line 3: invalid regex"));
+    }
+
+    #[test]
+    fn test_boundaries() {
+        let word = Substitution::new("AI", "A I");
+        assert_eq!(
+            word.apply("AI SAID MAIN AI-powered"),
+            "A I SAID MAIN A I-powered"
+        );
+        let token = Substitution::new("AI", "A I").boundary(Boundary::Token);
+        assert_eq!(token.apply("AI AI-powered (AI)"), "A I AI-powered (A I)");
+        let hyphenated = Substitution::new("e-mail", "email").boundary(Boundary::Token);
+        assert_eq!(hyphenated.apply("e-mail re-e-mail"), "email re-e-mail");
+        let symbol = Substitution::new("C++", "C plus plus");
+        assert_eq!(symbol.apply("C++ and ABC++"), "C plus plus and ABC++");
+        let anywhere = Substitution::new("AI", "A I").boundary(Boundary::None);
+        assert_eq!(anywhere.apply("SAID"), "SA ID");
+    }
+
+    #[test]
+    fn test_longest_match_first() {
+        let table: SubstitutionTable = [
+            ("API", "A P I"),
+            ("REST API", "rest A P I"),
+            ("A P I", "never"),
+            ("JSON", "jay son"),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            table.apply("A REST API returns JSON. The API too."),
+            "A rest A P I returns jay son. The A P I too."
+        );
+
+        let mut table = SubstitutionTable::new();
+        table.insert_rule(Substitution::new("état", "etat").case(Case::Preserve));
+        table.insert("Rust", "Rust");
+        table.insert_rule(Substitution::new("rust", "corrosion").case(Case::Insensitive));
+        assert_eq!(
+            table.apply("ÉTAT État, Rust and rust"),
+            "ETAT Etat, Rust and corrosion"
+        );
+    }
+}
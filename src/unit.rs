@@ -0,0 +1,66 @@
+//! Units in which a chunk limit is measured.
+
+use serde::Deserialize;
+
+/// How the length of a chunk is counted against the limit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum Unit {
+    /// Unicode scalar values.
+    #[default]
+    Chars,
+    /// UTF-8 encoded bytes.
+    Bytes,
+    /// Whitespace-separated words.
+    Words,
+    /// Estimated billing tokens: one per four characters of each word,
+    /// rounded up, and at least one per word.
+    Tokens,
+}
+
+names!(Unit, "unit", {
+    Chars => "chars",
+    Bytes => "bytes",
+    Words => "words",
+    Tokens => "tokens",
+});
+
+impl Unit {
+    /// Length of `text` in this unit.
+    pub fn measure(self, text: &str) -> usize {
+        match self {
+            Unit::Chars => text.chars().count(),
+            Unit::Bytes => text.len(),
+            Unit::Words => text.split_whitespace().count(),
+            Unit::Tokens => text
+                .split_whitespace()
+                .map(|word| word.chars().count().div_ceil(4))
+                .sum(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_measure_non_ascii() {
+        let text = "naïve café 😀";
+        assert_eq!(Unit::Chars.measure(text), 12);
+        assert_eq!(Unit::Bytes.measure(text), 17);
+        assert_eq!(Unit::Words.measure(text), 3);
+        assert_eq!(Unit::Tokens.measure(text), 4);
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!(Unit::Tokens.to_string(), "tokens");
+        assert_eq!("Words".parse(), Ok(Unit::Words));
+        assert_eq!(
+            "lines".parse::<Unit>(),
+            Err("unknown unit \"lines\", expected one of chars, bytes, words, tokens".to_string())
+        );
+    }
+}